@@ -1,6 +1,6 @@
 use pba::Model;
 
 fn main() {
-    let model = Model::<32>::new(0.0, 0.0, 1.0, None);
+    let model = Model::new((32, 32, 32), vec![0.0, 0.0], 1.0, None).unwrap();
     model.write_to_cif("out/mmcif/full.mmcif").unwrap();
 }
@@ -0,0 +1,24 @@
+use pba::upgrade_txt;
+
+/// Converts all .txt models in the given run directories into the binary format
+fn main() {
+    for dir in std::env::args().skip(1) {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                eprintln!("{}\ncould not read directory {}", err, dir);
+                continue;
+            }
+        };
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("txt") {
+                continue;
+            }
+            let target = path.with_extension("bin");
+            if let Err(err) = upgrade_txt(&path, &target) {
+                eprintln!("{}\ncould not upgrade {}", err, path.display())
+            }
+        }
+    }
+}
@@ -0,0 +1,29 @@
+use pba::{Model, WangLandau};
+
+const SIZE: (usize, usize, usize) = (6, 6, 6);
+const FILL_FRAC: f32 = 2.0 / 3.0;
+const J_2: f32 = 1.0;
+
+fn main() {
+    let model = Model::new(SIZE, vec![0.0, 0.0], FILL_FRAC, None).unwrap();
+    let mut sampler = WangLandau::new(model, 0.8);
+    let dos = sampler.run(1e-5, 20, 100_000_000).unwrap();
+    dos.write_to_csv("out/dos.csv").unwrap();
+
+    println!("j_prime,temp,free_energy,energy,entropy,heat_capacity");
+    for j_prime in [0.0, 2.0, 4.0, 6.0] {
+        for i in 0..40 {
+            let temp = (4.0 - 6.0 * i as f64 / 39.0).exp() * J_2 as f64;
+            let thermo = dos.thermodynamics(&[j_prime * J_2, J_2], temp);
+            println!(
+                "{},{},{},{},{},{}",
+                j_prime,
+                temp,
+                thermo.free_energy,
+                thermo.energy,
+                thermo.entropy,
+                thermo.heat_capacity
+            );
+        }
+    }
+}
@@ -5,6 +5,7 @@ import re
 import os
 import subprocess
 import multiprocessing
+import tomllib
 import numpy as np
 import h5py
 from matplotlib import pyplot as plt
@@ -235,14 +236,32 @@ def get_sf_string(mmcif_file: str) -> str:
         return None
 
 
-def get_runs() -> list[str]:
+def read_manifest(run: str) -> dict | None:
     """
-    returns a list of the names of the runs
+    reads the manifest of a run containing its parameters, seeds and produced files
+    older runs have no manifest
+    """
+    path = f"out/models/{run}/manifest.toml"
+    if not os.path.exists(path):
+        return None
+    with open(path, mode="rb") as file:
+        return tomllib.load(file)
+
+
+def get_runs(finished_only: bool = True) -> list[str]:
+    """
+    returns a sorted list of the names of the runs
+    runs with a manifest are only returned if they finished, unless finished_only is False
     """
     names = []
     for name in os.listdir("out/mmcif"):
-        if os.path.isdir(f"out/mmcif/{name}"):
-            names.append(name)
+        if not os.path.isdir(f"out/mmcif/{name}"):
+            continue
+        manifest = read_manifest(name)
+        if finished_only and manifest is not None and "end_time" not in manifest:
+            continue
+        names.append(name)
+    names.sort()
     return names
 
 
@@ -254,6 +273,28 @@ def get_file_names(run: str) -> list[str]:
     return list(map(lambda string: string.removesuffix(".mmcif"), names))
 
 
+def read_supercells(run: str, name: str | None = None) -> np.ndarray:
+    """
+    reads the number of supercells along a, b and c from the manifest of a run
+    or from the header of the csv file for older runs,
+    the oldest runs only contain a single value for cubic models
+    if a run contains several sizes the name of the file is needed, which ends in _s_{a}x{b}x{c}
+    """
+    manifest = read_manifest(run)
+    if manifest is not None:
+        size = np.array(manifest["parameters"]["size"])
+        if size.ndim == 1:
+            return size // 2
+        assert name is not None, "the run contains several sizes"
+        return np.array([int(n) for n in name.split("_s_")[-1].split("x")]) // 2
+    with open(f"out/csv/{run}.csv", mode="r", encoding="utf8") as file:
+        file.readline()
+        words = file.readline().split(" ")
+    if words[1].isdigit():
+        return np.array([int(word) for word in words[:3]])
+    return np.array([int(words[0])] * 3)
+
+
 def analyze_mmcif(run: str, name: str, supercells: str):
     """
     calculates all diffraction patterns of a run and saves them as .h5 files in the yell format
@@ -271,17 +312,13 @@ def analyze_run(run: str):
     """
     calculates all diffraction patterns of a run and saves them as .h5 files in the yell format
     """
-    with open(f"out/csv/{run}.csv", mode="r", encoding="utf8") as file:
-        file.readline()
-        supercells = int(file.readline().split(" ")[0])
-    supercells = np.array([supercells] * 3)
     os.makedirs(f"out/h5/{run}", exist_ok=True)
     names = get_file_names(run)
     lenght = len(names)
     for i, name in enumerate(names):
         print()
         print(f"file {i+1} of {lenght}")
-        analyze_mmcif(run, name, supercells)
+        analyze_mmcif(run, name, read_supercells(run, name))
 
 
 def analyze_mmcif_wrapper(args):
@@ -298,17 +335,13 @@ def analyze_run_parallel(run: str):
     and saves them as .h5 files in the yell format
     Additionally, saves the hk0 section as pngs
     """
-    with open(f"out/csv/{run}.csv", mode="r", encoding="utf8") as file:
-        file.readline()
-        supercells = int(file.readline().split(" ")[0])
-    supercells = np.array([supercells] * 3)
     os.makedirs(f"out/h5/{run}", exist_ok=True)
     os.makedirs(f"out/hk0/{run}", exist_ok=True)
     names = get_file_names(run)
 
     num_processes = multiprocessing.cpu_count()
     pool = multiprocessing.Pool(processes=num_processes)
-    args_list = [(run, name, supercells) for name in names]
+    args_list = [(run, name, read_supercells(run, name)) for name in names]
     pool.map(analyze_mmcif_wrapper, args_list)
     pool.close()
     pool.join()
@@ -383,7 +416,8 @@ def get_sorted_energies_and_temps(run: str) -> tuple[list[str], list[str]]:
     temps = []
     energies = []
     for name in get_file_names(run):
-        _, j, _, t = name.split("_")
+        # sweeps over more parameters append them to the name
+        j, t = name.split("_")[1:4:2]
         if t not in temps:
             temps.append(t)
         if j not in energies:
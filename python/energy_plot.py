@@ -7,7 +7,7 @@ import pandas as pd
 import matplotlib.pyplot as plt
 from matplotlib import rcParams
 
-from data_analysis import find_closest_point, Diffraction
+from data_analysis import find_closest_point, get_runs, Diffraction
 
 rcParams["font.family"] = "serif"
 # rcParams["font.serif"] = ["Noto Serif Regular"]
@@ -24,8 +24,8 @@ rcParams["font.family"] = "serif"
 #         file = files[-1]
 #     case num:
 #         file = files[int(num) - 1]
-run = "2023-11-16_17-01"
-file = "2023-11-16_17-01.csv"
+run = get_runs()[-1]
+file = f"{run}.csv"
 
 df = pd.read_csv(f"out/csv/{file}", header=3)
 
@@ -34,8 +34,7 @@ width = len(df["j_prime"].unique())
 height = len(df["temp"].unique())
 
 energies = df["energy"].values.reshape(width, height)
-variances = df["variance"].values
-heat_capacity = (variances / df["temp"].values ** 2).reshape(width, height)
+heat_capacity = df["heat_capacity"].values.reshape(width, height)
 temps = df["temp"].values.reshape(width, height)
 d_energy = np.gradient(energies, axis=1) / np.gradient(temps, axis=1)
 
@@ -1,82 +1,73 @@
 use std::ops::{Index, IndexMut};
-/// A 3D grid type that is Copy and allows indexes to "wrap around"
+/// A 3D grid with a size chosen at runtime that allows indexes to "wrap around"
 #[derive(Clone, Debug)]
-pub struct Array3d<T, const W: usize, const H: usize, const D: usize> {
-    pub grid: Box<[[[T; W]; H]; D]>,
+pub struct Array3d<T> {
+    width: usize,
+    height: usize,
+    depth: usize,
+    grid: Vec<T>,
 }
 
-impl<T, const W: usize, const H: usize, const D: usize> Array3d<T, W, H, D>
+impl<T> Array3d<T>
 where
     T: Copy + Default + Clone,
 {
-    pub fn new() -> Self {
+    pub fn new(width: usize, height: usize, depth: usize) -> Self {
         Self {
-            grid: Box::new([[[T::default(); W]; H]; D]),
+            width,
+            height,
+            depth,
+            grid: vec![T::default(); width * height * depth],
         }
     }
 }
 
-impl<T, const W: usize, const H: usize, const D: usize> Index<(isize, isize, isize)>
-    for Array3d<T, W, H, D>
-{
-    type Output = T;
+impl<T> Array3d<T> {
+    /// The extents of the grid as (width, height, depth)
+    pub fn dims(&self) -> (usize, usize, usize) {
+        (self.width, self.height, self.depth)
+    }
 
-    fn index(&self, index: (isize, isize, isize)) -> &Self::Output {
+    /// Converts a wrapped index to the position in the flat storage
+    #[inline]
+    fn flat_index(&self, index: (isize, isize, isize)) -> usize {
         let (x, y, z) = index;
-        let x = x.rem_euclid(W as isize);
-        let y = y.rem_euclid(H as isize);
-        let z = z.rem_euclid(D as isize);
-        // Safety this is safe because of the above rem
-        unsafe {
-            (*self.grid)
-                .get_unchecked(z as usize)
-                .get_unchecked(y as usize)
-                .get_unchecked(x as usize)
-        }
+        let x = x.rem_euclid(self.width as isize) as usize;
+        let y = y.rem_euclid(self.height as isize) as usize;
+        let z = z.rem_euclid(self.depth as isize) as usize;
+        (z * self.height + y) * self.width + x
     }
 }
 
-impl<T, const W: usize, const H: usize, const D: usize> IndexMut<(isize, isize, isize)>
-    for Array3d<T, W, H, D>
-{
-    fn index_mut(&mut self, index: (isize, isize, isize)) -> &mut Self::Output {
-        let (x, y, z) = index;
-        let x = x.rem_euclid(W as isize);
-        let y = y.rem_euclid(H as isize);
-        let z = z.rem_euclid(D as isize);
-        // Safety this is safe because of the above rem
-        unsafe {
-            (*self.grid)
-                .get_unchecked_mut(z as usize)
-                .get_unchecked_mut(y as usize)
-                .get_unchecked_mut(x as usize)
-        }
+impl<T> Index<(isize, isize, isize)> for Array3d<T> {
+    type Output = T;
+
+    fn index(&self, index: (isize, isize, isize)) -> &Self::Output {
+        let idx = self.flat_index(index);
+        // Safety this is safe because of the rem in flat_index
+        unsafe { self.grid.get_unchecked(idx) }
     }
 }
 
-impl<T: Default + Copy, const W: usize, const H: usize, const D: usize> Default
-    for Array3d<T, W, H, D>
-{
-    fn default() -> Self {
-        Self::new()
+impl<T> IndexMut<(isize, isize, isize)> for Array3d<T> {
+    fn index_mut(&mut self, index: (isize, isize, isize)) -> &mut Self::Output {
+        let idx = self.flat_index(index);
+        // Safety this is safe because of the rem in flat_index
+        unsafe { self.grid.get_unchecked_mut(idx) }
     }
 }
 
-impl<T, const W: usize, const H: usize, const D: usize> Array3d<T, W, H, D> {
+impl<T> Array3d<T> {
     pub fn as_flat_slice(&self) -> &[T] {
-        // Safety: the memory layout of [[T; N]; N] is the same as [T]
-        // TODO zero sized types
-        unsafe { std::slice::from_raw_parts(self.grid.as_ptr().cast(), W * H * D) }
+        &self.grid
     }
 
     pub fn as_flat_slice_mut(&mut self) -> &mut [T] {
-        // Safety: the memory layout of [[T; N]; N] is the same as [T]
-        // TODO zero sized types
-        unsafe { std::slice::from_raw_parts_mut(self.grid.as_mut_ptr().cast(), W * H * D) }
+        &mut self.grid
     }
 }
 
-impl<const W: usize, const H: usize, const D: usize> Array3d<i8, W, H, D> {
+impl Array3d<i8> {
     pub fn as_string(&self) -> String {
         let mut out = String::new();
         for val in self.as_flat_slice() {
@@ -85,16 +76,20 @@ impl<const W: usize, const H: usize, const D: usize> Array3d<i8, W, H, D> {
         out.pop();
         out
     }
+}
 
-    pub fn from_string(string: &str) -> Result<Self, std::num::ParseIntError> {
-        let split = string.split_whitespace();
-        let mut grid = Self::new();
-        let mut counter = 0;
-        for (v, s) in grid.as_flat_slice_mut().iter_mut().zip(split.into_iter()) {
-            *v = s.parse()?;
-            counter += 1;
-        }
-        assert_eq!(W * H * D, counter);
-        Ok(grid)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indexes_wrap_around() {
+        let mut grid = Array3d::<i8>::new(4, 6, 8);
+        grid[(1, 2, 3)] = 5;
+        assert_eq!(grid[(1 + 4, 2 - 6, 3 + 16)], 5);
+        assert_eq!(grid[(-3, -4, -5)], 5);
+        grid[(-1, -1, -1)] = 7;
+        assert_eq!(grid[(3, 5, 7)], 7);
+        assert_eq!(grid.as_flat_slice().iter().filter(|v| **v != 0).count(), 2);
     }
 }
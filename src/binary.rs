@@ -0,0 +1,272 @@
+//! A compact binary format for models.
+//! Only the cyanometalate sublattice is stored as a bitset, since the metal sites
+//! and the interstitial positions are fixed.
+//!
+//! Layout, all numbers are little endian:
+//! - the magic bytes `PBAM`
+//! - the version as u16
+//! - the dims as 3 u32
+//! - the number of couplings as u32 followed by the couplings as f32
+//! - the good, bad and rejected moves as u32
+//! - the bitset of the sublattice in the order of `cyanometalate_sites`, 1 is a cyanometalate
+//! - the FNV-1a hash of all preceding bytes as u64
+use std::io::Write;
+use std::path::Path;
+
+use crate::{
+    cyanometalate_sites, Array3d, Hamiltonian, Model, ModelError, PairHamiltonian, MAX_SHELLS,
+};
+
+const MAGIC: &[u8; 4] = b"PBAM";
+const VERSION: u16 = 1;
+
+impl Model<PairHamiltonian> {
+    /// Saves the model in the binary format.
+    /// note that the state of the rng is not preserved in this step
+    pub fn save_to_binary(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        let (a, b, c) = self.dims;
+        for extent in [a, b, c] {
+            bytes.extend_from_slice(&(extent as u32).to_le_bytes());
+        }
+        let couplings = self.hamiltonian.couplings();
+        bytes.extend_from_slice(&(couplings.len() as u32).to_le_bytes());
+        for j in couplings {
+            bytes.extend_from_slice(&j.to_le_bytes());
+        }
+        for counter in [self.good_moves, self.bad_moves, self.rejected_moves] {
+            bytes.extend_from_slice(&counter.to_le_bytes());
+        }
+
+        let mut bitset = vec![0u8; self.sites().div_ceil(8)];
+        for (n, idx) in cyanometalate_sites(self.dims).enumerate() {
+            if self.grid[idx] == 1 {
+                bitset[n / 8] |= 1 << (n % 8);
+            }
+        }
+        bytes.extend_from_slice(&bitset);
+        bytes.extend_from_slice(&fnv1a(&bytes).to_le_bytes());
+
+        let mut file = std::fs::File::create(path)?;
+        file.write_all(&bytes)?;
+        file.flush()
+    }
+
+    /// Reads a model from the binary format.
+    /// The magic bytes, the version, the size of the data and the checksum are validated.
+    /// note that the state of the rng is not preserved
+    pub fn from_binary(path: impl AsRef<Path>) -> Result<Self, ModelError> {
+        let bytes = std::fs::read(path)?;
+        if bytes.len() < MAGIC.len() + 8 || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(ModelError::BadMagic);
+        }
+        let (data, checksum) = bytes.split_at(bytes.len() - 8);
+        let checksum = u64::from_le_bytes(checksum.try_into().expect("split at 8 bytes"));
+        let found = fnv1a(data);
+        if found != checksum {
+            return Err(ModelError::ChecksumMismatch {
+                expected: checksum,
+                found,
+            });
+        }
+
+        let mut reader = Reader {
+            data,
+            pos: MAGIC.len(),
+        };
+        let version = u16::from_le_bytes(reader.take()?);
+        if version > VERSION {
+            return Err(ModelError::UnsupportedVersion {
+                found: version,
+                supported: VERSION,
+            });
+        }
+        let a = u32::from_le_bytes(reader.take()?) as usize;
+        let b = u32::from_le_bytes(reader.take()?) as usize;
+        let c = u32::from_le_bytes(reader.take()?) as usize;
+        if !(a.is_multiple_of(2) && b.is_multiple_of(2) && c.is_multiple_of(2)) {
+            return Err(ModelError::OddDims { dims: (a, b, c) });
+        }
+        let shells = u32::from_le_bytes(reader.take()?) as usize;
+        if shells > MAX_SHELLS {
+            return Err(ModelError::TooManyShells {
+                found: shells,
+                supported: MAX_SHELLS,
+            });
+        }
+        let mut couplings = Vec::with_capacity(shells);
+        for _ in 0..shells {
+            couplings.push(f32::from_le_bytes(reader.take()?));
+        }
+        let good_moves = u32::from_le_bytes(reader.take()?);
+        let bad_moves = u32::from_le_bytes(reader.take()?);
+        let rejected_moves = u32::from_le_bytes(reader.take()?);
+
+        let dims = (a, b, c);
+        let sites = a * b * c / 2;
+        let bitset = reader.rest();
+        if bitset.len() != sites.div_ceil(8) {
+            return Err(ModelError::SizeMismatch {
+                field: "sublattice bitset",
+                expected: sites.div_ceil(8),
+                found: bitset.len(),
+            });
+        }
+        let mut grid = Array3d::new(a, b, c);
+        for (n, idx) in cyanometalate_sites(dims).enumerate() {
+            grid[idx] = if bitset[n / 8] & (1 << (n % 8)) != 0 {
+                1
+            } else {
+                -1
+            };
+        }
+
+        let hamiltonian = PairHamiltonian::new(couplings);
+        hamiltonian.check_dims(dims)?;
+        let mut out = Self {
+            dims,
+            grid,
+            hamiltonian,
+            metalates: 0,
+            rng: rand::SeedableRng::from_entropy(),
+            good_moves,
+            bad_moves,
+            rejected_moves,
+        };
+        out.metalates = out
+            .grid
+            .as_flat_slice()
+            .iter()
+            .filter(|val| **val == 1)
+            .count();
+        out.calc_sums();
+        Ok(out)
+    }
+}
+
+/// Converts a model from the .txt format into the binary format
+pub fn upgrade_txt(
+    txt_path: impl AsRef<Path>,
+    binary_path: impl AsRef<Path>,
+) -> Result<(), ModelError> {
+    Model::from_txt(txt_path)?.save_to_binary(binary_path)?;
+    Ok(())
+}
+
+/// A cursor over the bytes of a binary model
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    /// Takes the next N bytes
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], ModelError> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + N)
+            .ok_or(ModelError::SizeMismatch {
+                field: "file",
+                expected: self.pos + N,
+                found: self.data.len(),
+            })?;
+        self.pos += N;
+        Ok(bytes.try_into().expect("slice has length N"))
+    }
+
+    /// The remaining bytes
+    fn rest(&self) -> &[u8] {
+        &self.data[self.pos..]
+    }
+}
+
+/// The 64 bit FNV-1a hash
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Saves a model with a seeded configuration and returns the bytes of the file
+    fn saved_model(name: &str) -> (Model, Vec<u8>) {
+        let mut model = Model::new((4, 6, 8), vec![-1.0, 0.5], 0.4, Some("binary")).unwrap();
+        for _ in 0..100 {
+            model.monte_carlo_step(2.0);
+        }
+        let path = std::env::temp_dir().join(format!("pba_{}_{}.bin", name, std::process::id()));
+        model.save_to_binary(&path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        (model, bytes)
+    }
+
+    /// Writes the bytes to a file and reads them as a model
+    fn load(name: &str, bytes: &[u8]) -> Result<Model, ModelError> {
+        let path = std::env::temp_dir().join(format!("pba_{}_{}.bin", name, std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        let model = Model::from_binary(&path);
+        std::fs::remove_file(&path).unwrap();
+        model
+    }
+
+    #[test]
+    fn round_trip() {
+        let (model, bytes) = saved_model("round_trip");
+        let loaded = load("round_trip_load", &bytes).unwrap();
+        assert_eq!(loaded.dims(), model.dims());
+        assert_eq!(
+            loaded.hamiltonian().couplings(),
+            model.hamiltonian().couplings()
+        );
+        assert_eq!(loaded.counters(), model.counters());
+        assert_eq!(loaded.metalates(), model.metalates());
+        assert_eq!(loaded.grid.as_flat_slice(), model.grid.as_flat_slice());
+        assert_eq!(loaded.get_hamiltonian(), model.get_hamiltonian());
+    }
+
+    #[test]
+    fn rejects_corrupted_files() {
+        let (_, mut bytes) = saved_model("corrupted");
+        let last_site = bytes.len() - 9;
+        bytes[last_site] ^= 1;
+        assert!(matches!(
+            load("corrupted_load", &bytes),
+            Err(ModelError::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let (_, mut bytes) = saved_model("version");
+        bytes[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        let data = bytes.len() - 8;
+        let checksum = fnv1a(&bytes[..data]);
+        bytes[data..].copy_from_slice(&checksum.to_le_bytes());
+        assert!(matches!(
+            load("version_load", &bytes),
+            Err(ModelError::UnsupportedVersion {
+                found: 2,
+                supported: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let (_, mut bytes) = saved_model("magic");
+        bytes[..4].copy_from_slice(b"PBAX");
+        assert!(matches!(
+            load("magic_load", &bytes),
+            Err(ModelError::BadMagic)
+        ));
+    }
+}
@@ -0,0 +1,215 @@
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
+use std::io::Write;
+use std::path::Path;
+
+use crate::{LineReader, Model, ModelError, PairHamiltonian, ParallelTempering};
+
+/// A snapshot of a running cooling schedule.
+/// In contrast to the .txt format of the model this also preserves the state of the rng,
+/// so a run resumed from a checkpoint gives bit-identical results.
+#[derive(Debug)]
+pub struct Checkpoint {
+    /// The model including the state of its rng
+    pub model: Model<PairHamiltonian>,
+    /// The index of the next temperature in the schedule
+    pub schedule_pos: usize,
+    /// The temperature the model was last simulated at
+    pub temp: f32,
+}
+
+impl Checkpoint {
+    /// Writes the checkpoint to a file.
+    /// The checkpoint specific lines come first and are followed by the model in the .txt format
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let mut file = std::fs::File::create(path)?;
+        writeln!(file, "{} schedule position", self.schedule_pos)?;
+        writeln!(file, "{} temperature", self.temp)?;
+        write_rng(&mut file, &self.model.rng)?;
+        self.model.write_txt(&mut file)?;
+        file.flush()
+    }
+
+    /// Reads a checkpoint written by save
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ModelError> {
+        let string = std::fs::read_to_string(path)?;
+        let mut reader = LineReader::new(&string);
+        let schedule_pos = reader.parse_next("schedule position")?;
+        let temp = reader.parse_next("temperature")?;
+        let rng = parse_rng(&mut reader)?;
+
+        let mut model = Model::parse_txt(&mut reader)?;
+        model.rng = rng;
+        Ok(Self {
+            model,
+            schedule_pos,
+            temp,
+        })
+    }
+}
+
+/// A snapshot of a parallel tempering chain at the end of its equilibration.
+/// Like a Checkpoint it preserves the states of all rngs,
+/// so a chain resumed from it gives bit-identical results.
+pub struct TemperingCheckpoint {
+    /// The driver including all replicas and the states of their rngs
+    pub tempering: ParallelTempering,
+    /// The number of sweeps the chain was equilibrated for
+    pub eq_sweeps: usize,
+}
+
+impl TemperingCheckpoint {
+    /// Writes the checkpoint to a file.
+    /// The checkpoint specific lines come first and are followed by the driver and its replicas
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let mut file = std::fs::File::create(path)?;
+        writeln!(file, "{} equilibration sweeps", self.eq_sweeps)?;
+        self.tempering.write_txt(&mut file)?;
+        file.flush()
+    }
+
+    /// Reads a checkpoint written by save
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ModelError> {
+        let string = std::fs::read_to_string(path)?;
+        let mut reader = LineReader::new(&string);
+        let eq_sweeps = reader.parse_next("equilibration sweeps")?;
+        let tempering = ParallelTempering::parse_txt(&mut reader)?;
+        Ok(Self {
+            tempering,
+            eq_sweeps,
+        })
+    }
+}
+
+/// Writes the seed, stream and word position of the rng, one per line
+pub(crate) fn write_rng(file: &mut impl Write, rng: &ChaCha12Rng) -> std::io::Result<()> {
+    writeln!(file, "{} rng seed", to_hex(&rng.get_seed()))?;
+    writeln!(file, "{} rng stream", rng.get_stream())?;
+    writeln!(file, "{} rng word position", rng.get_word_pos())
+}
+
+/// Recreates the rng from the lines written by write_rng
+pub(crate) fn parse_rng(reader: &mut LineReader) -> Result<ChaCha12Rng, ModelError> {
+    let hex: String = reader.parse_next("rng seed")?;
+    let seed = from_hex(&hex).ok_or(ModelError::InvalidField {
+        line: reader.line,
+        field: "rng seed",
+        value: hex,
+    })?;
+    let mut rng = ChaCha12Rng::from_seed(seed);
+    rng.set_stream(reader.parse_next("rng stream")?);
+    rng.set_word_pos(reader.parse_next("rng word position")?);
+    Ok(rng)
+}
+
+/// Formats the bytes as a hexadecimal string
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Parses a seed from a hexadecimal string
+fn from_hex(string: &str) -> Option<[u8; 32]> {
+    let mut seed = [0; 32];
+    if string.len() != 2 * seed.len() || !string.is_ascii() {
+        return None;
+    }
+    for (i, byte) in seed.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&string[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(seed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn resume_is_bit_identical() {
+        let new_model = || Model::new((4, 4, 6), vec![-1.0, 0.5], 0.5, Some("checkpoint")).unwrap();
+        let mut uninterrupted = new_model();
+        for _ in 0..400 {
+            uninterrupted.monte_carlo_step(1.5);
+        }
+
+        let mut model = new_model();
+        for _ in 0..200 {
+            model.monte_carlo_step(1.5);
+        }
+        let path = std::env::temp_dir().join(format!("pba_{}.checkpoint", std::process::id()));
+        Checkpoint {
+            model,
+            schedule_pos: 3,
+            temp: 1.5,
+        }
+        .save(&path)
+        .unwrap();
+        let checkpoint = Checkpoint::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((checkpoint.schedule_pos, checkpoint.temp), (3, 1.5));
+        let mut resumed = checkpoint.model;
+        for _ in 0..200 {
+            resumed.monte_carlo_step(1.5);
+        }
+
+        assert_eq!(
+            resumed.grid.as_flat_slice(),
+            uninterrupted.grid.as_flat_slice()
+        );
+        assert_eq!(resumed.counters(), uninterrupted.counters());
+        assert_eq!(resumed.get_hamiltonian(), uninterrupted.get_hamiltonian());
+        assert_eq!(resumed.rng.gen::<u64>(), uninterrupted.rng.gen::<u64>());
+    }
+
+    #[test]
+    fn tempering_resume_is_bit_identical() {
+        let new_tempering = || {
+            let replicas = (0..3)
+                .map(|i| {
+                    let seed = format!("tempering_{}", i);
+                    Model::new((4, 4, 4), vec![-1.0, 0.5], 0.5, Some(&seed)).unwrap()
+                })
+                .collect();
+            ParallelTempering::new(replicas, vec![1.0, 2.0, 4.0], None, Some("tempering"))
+        };
+        let rounds = |tempering: &mut ParallelTempering, n: usize| {
+            let mut nothing = vec![(); 3];
+            for _ in 0..n {
+                tempering.steps(64, &mut nothing, |_, _| ());
+                tempering.exchange();
+            }
+        };
+        let mut uninterrupted = new_tempering();
+        rounds(&mut uninterrupted, 40);
+
+        let mut tempering = new_tempering();
+        rounds(&mut tempering, 20);
+        let path =
+            std::env::temp_dir().join(format!("pba_tempering_{}.checkpoint", std::process::id()));
+        TemperingCheckpoint {
+            tempering,
+            eq_sweeps: 20,
+        }
+        .save(&path)
+        .unwrap();
+        let checkpoint = TemperingCheckpoint::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(checkpoint.eq_sweeps, 20);
+        let mut resumed = checkpoint.tempering;
+        rounds(&mut resumed, 20);
+
+        assert_eq!(resumed.temps(), uninterrupted.temps());
+        assert_eq!(resumed.acceptance_rates(), uninterrupted.acceptance_rates());
+        for (resumed, uninterrupted) in resumed.replicas().iter().zip(uninterrupted.replicas()) {
+            assert_eq!(
+                resumed.grid.as_flat_slice(),
+                uninterrupted.grid.as_flat_slice()
+            );
+            assert_eq!(resumed.counters(), uninterrupted.counters());
+            assert_eq!(
+                resumed.rng.clone().gen::<u64>(),
+                uninterrupted.rng.clone().gen::<u64>()
+            );
+        }
+    }
+}
@@ -0,0 +1,706 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::{Hamiltonian, PairHamiltonian, Plane, Rendering};
+
+/// The values of a single parameter of a sweep.
+/// In toml a grid is either a number, a list of numbers
+/// or a table with start, end, steps and optionally `log = true`
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum Grid {
+    /// A single value
+    Single(f32),
+    /// Explicit values in the order they are visited
+    List(Vec<f32>),
+    /// Values between start and end, both included,
+    /// which are evenly spaced or, if log is set, geometrically spaced
+    Range {
+        start: f32,
+        end: f32,
+        steps: u32,
+        #[serde(default)]
+        log: bool,
+    },
+}
+
+impl Grid {
+    /// Evenly spaced values between start and end
+    pub fn linear(start: f32, end: f32, steps: u32) -> Self {
+        Self::Range {
+            start,
+            end,
+            steps,
+            log: false,
+        }
+    }
+
+    /// Geometrically spaced values between start and end, which need to be positive
+    pub fn log(start: f32, end: f32, steps: u32) -> Self {
+        Self::Range {
+            start,
+            end,
+            steps,
+            log: true,
+        }
+    }
+
+    /// All values of the grid
+    pub fn values(&self) -> Vec<f32> {
+        match self {
+            Grid::Single(value) => vec![*value],
+            Grid::List(values) => values.clone(),
+            Grid::Range {
+                start,
+                steps: 0..=1,
+                ..
+            } => vec![*start],
+            Grid::Range {
+                start,
+                end,
+                steps,
+                log: false,
+            } => (0..*steps)
+                .map(|i| i as f32 / (steps - 1) as f32 * (end - start) + start)
+                .collect(),
+            Grid::Range {
+                start,
+                end,
+                steps,
+                log: true,
+            } => (0..*steps)
+                .map(|i| {
+                    (i as f32 / (steps - 1) as f32 * (end.ln() - start.ln()) + start.ln()).exp()
+                })
+                .collect(),
+        }
+    }
+
+    /// Checks that the grid has values, that a geometric range has positive bounds
+    /// and that no value appears twice, as the points of a sweep are named by their values
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Grid::List(values) if values.is_empty() => return Err("the list is empty".into()),
+            Grid::Range { steps: 0, .. } => return Err("the range has no steps".into()),
+            Grid::Range {
+                start,
+                end,
+                log: true,
+                ..
+            } if !(*start > 0.0 && *end > 0.0) => {
+                return Err(format!(
+                    "the geometric range from {} to {} needs positive bounds",
+                    start, end
+                ))
+            }
+            _ => (),
+        }
+        let mut values = self.values();
+        values.sort_by(f32::total_cmp);
+        match values.windows(2).find(|pair| pair[0] == pair[1]) {
+            Some(pair) => Err(format!("the value {} appears more than once", pair[0])),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Parses a grid from the command line.
+/// `1.5` is a single value, `0,0.5,2` a list, `6:0:16` evenly spaced values
+/// from 6 to 0 in 16 steps and `0.1:10:20:log` geometrically spaced values
+impl FromStr for Grid {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let number = |value: &str| {
+            value
+                .trim()
+                .parse::<f32>()
+                .map_err(|_| format!("'{}' is not a number", value))
+        };
+        if s.contains(':') {
+            let parts: Vec<_> = s.split(':').collect();
+            let log = match parts.get(3) {
+                None => false,
+                Some(&"log") => true,
+                Some(other) => return Err(format!("unknown spacing '{}'", other)),
+            };
+            if parts.len() < 3 || parts.len() > 4 {
+                return Err(format!("expected start:end:steps[:log] but got '{}'", s));
+            }
+            let steps = parts[2]
+                .trim()
+                .parse()
+                .map_err(|_| format!("'{}' is not a number of steps", parts[2]))?;
+            Ok(Grid::Range {
+                start: number(parts[0])?,
+                end: number(parts[1])?,
+                steps,
+                log,
+            })
+        } else if s.contains(',') {
+            Ok(Grid::List(
+                s.split(',').map(number).collect::<Result<_, _>>()?,
+            ))
+        } else {
+            Ok(Grid::Single(number(s)?))
+        }
+    }
+}
+
+/// The number of grid points along a, b and c of the models of a sweep
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum SizeGrid {
+    Single((usize, usize, usize)),
+    List(Vec<(usize, usize, usize)>),
+}
+
+impl SizeGrid {
+    /// All sizes of the grid
+    pub fn values(&self) -> Vec<(usize, usize, usize)> {
+        match self {
+            SizeGrid::Single(size) => vec![*size],
+            SizeGrid::List(sizes) => sizes.clone(),
+        }
+    }
+}
+
+/// The parameter that is changed along a chain, all other parameters are fixed for a chain
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Anneal {
+    /// Every chain is cooled through the temperatures
+    Temp,
+    /// Every chain is taken through the values of J' at a fixed temperature
+    JPrime,
+    /// Every chain is taken through the values of J_2 at a fixed temperature
+    J2,
+    /// Every point is simulated with its own model
+    Independent,
+}
+
+impl FromStr for Anneal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.replace('-', "_").as_str() {
+            "temp" => Ok(Anneal::Temp),
+            "j_prime" => Ok(Anneal::JPrime),
+            "j_2" | "j2" => Ok(Anneal::J2),
+            "independent" => Ok(Anneal::Independent),
+            _ => Err(format!(
+                "expected temp, j_prime, j_2 or independent but got '{}'",
+                s
+            )),
+        }
+    }
+}
+
+/// A point of a sweep
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Point {
+    pub size: (usize, usize, usize),
+    pub fill_frac: f32,
+    pub j_2: f32,
+    pub j_prime: f32,
+    pub temp: f32,
+    /// The couplings of the third, fourth and fifth shell, if they are included
+    pub j_3: Option<f32>,
+    pub j_4: Option<f32>,
+    pub j_5: Option<f32>,
+}
+
+impl Point {
+    /// The number of cyanometalate sites
+    pub fn sites(&self) -> usize {
+        self.size.0 * self.size.1 * self.size.2 / 2
+    }
+
+    /// The couplings of the shells, J_1 = J' * J_2 followed by J_2 and the included further shells
+    pub fn couplings(&self) -> Vec<f32> {
+        let further = [self.j_3, self.j_4, self.j_5];
+        [self.j_prime * self.j_2, self.j_2]
+            .into_iter()
+            .chain(further.into_iter().map_while(|j| j))
+            .collect()
+    }
+}
+
+/// All parameters of a sweep.
+/// The points of the sweep are all combinations of the values of the grids
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SweepConfig {
+    /// The number of grid points along a, b and c
+    pub size: SizeGrid,
+    /// The fraction of cyanometalate sites that is occupied at the start
+    pub fill_frac: Grid,
+    /// The interaction energy of the next nearest neighbours
+    pub j_2: Grid,
+    /// The values of J' = J_1 / J_2
+    pub j_prime: Grid,
+    /// If set, the third shell of neighbours is included with these interaction energies
+    pub j_3: Option<Grid>,
+    /// If set, the fourth shell is included, this requires the third one
+    pub j_4: Option<Grid>,
+    /// If set, the fifth shell is included, this requires the fourth one
+    pub j_5: Option<Grid>,
+    /// The temperatures, they are absolute so J_2 can be changed at a fixed temperature
+    pub temp: Grid,
+    /// The parameter that is changed along a chain, its values are visited in the given order
+    pub anneal: Anneal,
+    /// The number of sweeps at every point before sampling,
+    /// with automatic equilibration this is the maximum
+    pub eq_epochs: usize,
+    /// If set, equilibration stops as soon as the energy is stationary
+    pub auto_equilibration: bool,
+    /// The minimal number of sweeps of the automatic equilibration
+    pub min_eq_epochs: usize,
+    /// The number of sweeps at every point that are sampled
+    pub epoch: usize,
+    /// The chemical potential of the cyanometalates.
+    /// If it is set, the composition is sampled grand canonically
+    pub chemical_potential: Option<f32>,
+    /// If set, every chain is simulated with one replica per temperature
+    /// and configurations are exchanged between neighbouring temperatures
+    /// instead of cooling a single model, this requires annealing along the temperature
+    pub tempering: bool,
+    /// The number of sweeps between two exchange attempts
+    pub exchange_interval: usize,
+    /// If set, the observables of every sweep are written to the trajectories directory
+    pub trajectories: bool,
+    /// If set, the histogram of the neighbour sums of every point is written
+    /// to the histograms directory for reweighting, this requires a fixed composition
+    pub histograms: bool,
+    /// If set, the Warren-Cowley parameters of all vectors up to this length in unit cells
+    /// are written to the sro directory
+    pub sro_radius: Option<f32>,
+    /// The number of sampled sweeps between two snapshots of the short range order
+    pub sro_interval: usize,
+    /// If set, |F(hkl)|^2 up to this hkl is averaged over snapshots of every point
+    /// and written to the h5 directory in the Yell format
+    pub scattering_max_hkl: Option<f32>,
+    /// The number of sampled sweeps between two snapshots of the diffuse scattering
+    pub scattering_interval: usize,
+    /// If set, this section of the scattering of every point is written to the sections directory
+    /// and the sections of all points are combined into a map of J' and temperature
+    /// in the maps directory, this requires scattering_max_hkl
+    pub section: Option<Plane>,
+    /// If set, the sections are averaged over the cubic symmetry
+    pub symmetrize: bool,
+    /// The percentiles of the logarithm of the intensities that are clamped
+    /// at the low and at the high end of the sections and maps
+    pub clamp_percentiles: [f32; 2],
+    /// The seed of the random number generators, every chain derives its own seed from it
+    pub seed: Option<String>,
+    /// The directory containing the csv, mmcif, models and trajectories directories
+    pub output: String,
+}
+
+impl Default for SweepConfig {
+    fn default() -> Self {
+        Self {
+            size: SizeGrid::Single((32, 32, 32)),
+            fill_frac: Grid::Single(2.0 / 3.0),
+            j_2: Grid::Single(1.0),
+            j_prime: Grid::linear(6.0, 0.0, 16),
+            j_3: None,
+            j_4: None,
+            j_5: None,
+            temp: Grid::log(4f32.exp(), (-2f32).exp(), 40),
+            anneal: Anneal::Temp,
+            eq_epochs: 500,
+            auto_equilibration: true,
+            min_eq_epochs: 50,
+            epoch: 500,
+            chemical_potential: None,
+            tempering: false,
+            exchange_interval: 1,
+            trajectories: false,
+            histograms: false,
+            sro_radius: None,
+            sro_interval: 10,
+            scattering_max_hkl: None,
+            scattering_interval: 10,
+            section: None,
+            symmetrize: false,
+            clamp_percentiles: [0.0, 0.0],
+            seed: None,
+            output: "out".to_string(),
+        }
+    }
+}
+
+impl SweepConfig {
+    /// Reads the config from a toml file, missing values are taken from the default
+    pub fn from_toml(path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    /// Writes the config to a toml file
+    pub fn write_toml(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn std::error::Error>> {
+        std::fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Checks that the parameters describe a sweep that can be run
+    pub fn validate(&self) -> Result<(), String> {
+        let grids = [
+            ("fill_frac", Some(&self.fill_frac)),
+            ("j_2", Some(&self.j_2)),
+            ("j_prime", Some(&self.j_prime)),
+            ("temp", Some(&self.temp)),
+            ("j_3", self.j_3.as_ref()),
+            ("j_4", self.j_4.as_ref()),
+            ("j_5", self.j_5.as_ref()),
+        ];
+        for (name, grid) in grids {
+            if let Some(grid) = grid {
+                grid.validate()
+                    .map_err(|err| format!("the grid of {}: {}", name, err))?;
+            }
+        }
+        let mut sizes = self.size.values();
+        sizes.sort();
+        if let Some(pair) = sizes.windows(2).find(|pair| pair[0] == pair[1]) {
+            return Err(format!("the size {:?} appears more than once", pair[0]));
+        }
+        if self.tempering && self.anneal != Anneal::Temp {
+            return Err("parallel tempering requires annealing along the temperature".into());
+        }
+        if self.histograms && self.chemical_potential.is_some() {
+            return Err("histograms can only be reweighted at a fixed composition".into());
+        }
+        if self.exchange_interval == 0 {
+            return Err("the exchange interval needs to be positive".into());
+        }
+        if self.tempering && self.epoch < 2 * self.exchange_interval {
+            // the exchanges alternate between the even and odd pairs,
+            // so it takes two of them until every pair had an attempt
+            return Err(format!(
+                "the epoch of {} sweeps needs to contain at least two exchanges every {} sweeps",
+                self.epoch, self.exchange_interval
+            ));
+        }
+        for (a, b, c) in self.size.values() {
+            if !(a.is_multiple_of(2) && b.is_multiple_of(2) && c.is_multiple_of(2)) {
+                return Err(format!("the size {:?} needs to be even", (a, b, c)));
+            }
+        }
+        if self.sro_interval == 0 {
+            return Err("the short range order interval needs to be positive".into());
+        }
+        if self.scattering_interval == 0 {
+            return Err("the scattering interval needs to be positive".into());
+        }
+        if let Some(max_hkl) = self.scattering_max_hkl {
+            if max_hkl < 0.0 {
+                return Err(format!("the largest hkl {} is negative", max_hkl));
+            }
+        }
+        if self.section.is_some() && self.scattering_max_hkl.is_none() {
+            return Err("sections require the scattering to be calculated".into());
+        }
+        let [low, high] = self.clamp_percentiles;
+        if low < 0.0 || high < 0.0 || low + high >= 100.0 {
+            return Err(format!(
+                "the clamped percentiles {} and {} need to be positive and leave values",
+                low, high
+            ));
+        }
+        if let Some(radius) = self.sro_radius {
+            for (a, b, c) in self.size.values() {
+                if 4.0 * radius > a.min(b).min(c) as f32 {
+                    return Err(format!(
+                        "the short range order radius {} does not fit into half of the size {:?}",
+                        radius,
+                        (a, b, c)
+                    ));
+                }
+            }
+        }
+        for fill_frac in self.fill_frac.values() {
+            if !(fill_frac > 0.0 && fill_frac < 1.0) {
+                return Err(format!("the fill fraction {} is not in (0, 1)", fill_frac));
+            }
+            // an empty or full lattice has no swap moves
+            for (a, b, c) in self.size.values() {
+                let sites = a * b * c / 2;
+                let metalates = (fill_frac * sites as f32).floor() as usize;
+                if metalates == 0 || metalates == sites {
+                    return Err(format!(
+                        "the fill fraction {} leaves {} of {} sites of the size {:?} occupied",
+                        fill_frac,
+                        metalates,
+                        sites,
+                        (a, b, c)
+                    ));
+                }
+            }
+        }
+        if let Some(temp) = self
+            .temp
+            .values()
+            .into_iter()
+            .find(|temp| *temp <= 0.0 || temp.is_nan())
+        {
+            return Err(format!("the temperature {} needs to be positive", temp));
+        }
+        if (self.j_4.is_some() && self.j_3.is_none()) || (self.j_5.is_some() && self.j_4.is_none())
+        {
+            return Err("the further shells need to be included in order of their distance".into());
+        }
+        let Some(point) = self.points().first().copied() else {
+            return Err("the sweep has no points".into());
+        };
+        // all points have the same shells
+        let hamiltonian = PairHamiltonian::new(point.couplings());
+        for size in self.size.values() {
+            hamiltonian
+                .check_dims(size)
+                .map_err(|err| err.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// All points of the sweep grouped into chains.
+    /// The points of a chain only differ in the annealed parameter
+    /// and are in the order the parameter is visited
+    pub fn chains(&self) -> Vec<Vec<Point>> {
+        let mut chains: Vec<Vec<Point>> = Vec::new();
+        for point in self.points() {
+            let chain = match self.anneal {
+                Anneal::Independent => None,
+                _ => chains
+                    .iter_mut()
+                    .find(|chain| self.chain_name(&chain[0]) == self.chain_name(&point)),
+            };
+            match chain {
+                Some(chain) => chain.push(point),
+                None => chains.push(vec![point]),
+            }
+        }
+        chains
+    }
+
+    /// All combinations of the values of the grids
+    pub fn points(&self) -> Vec<Point> {
+        // a shell that is not included has the single value None
+        let further = |grid: &Option<Grid>| match grid {
+            Some(grid) => grid.values().into_iter().map(Some).collect(),
+            None => vec![None],
+        };
+        let mut points = Vec::new();
+        for size in self.size.values() {
+            for fill_frac in self.fill_frac.values() {
+                for j_2 in self.j_2.values() {
+                    for j_3 in further(&self.j_3) {
+                        for j_4 in further(&self.j_4) {
+                            for j_5 in further(&self.j_5) {
+                                for j_prime in self.j_prime.values() {
+                                    for temp in self.temp.values() {
+                                        points.push(Point {
+                                            size,
+                                            fill_frac,
+                                            j_2,
+                                            j_prime,
+                                            temp,
+                                            j_3,
+                                            j_4,
+                                            j_5,
+                                        })
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        points
+    }
+
+    /// The name of the files of a point, e.g. `j_1_t_0.5`.
+    /// Parameters with more than one value are appended as `_j2_{}`, `_j3_{}`, `_j4_{}`, `_j5_{}`,
+    /// `_f_{}` and `_s_{a}x{b}x{c}`
+    pub fn point_name(&self, point: &Point) -> String {
+        self.name_parts(point, None).join("_")
+    }
+
+    /// The name of a chain, which is the name of its points without the annealed parameter
+    pub fn chain_name(&self, point: &Point) -> String {
+        self.name_parts(point, Some(self.anneal)).join("_")
+    }
+
+    /// The name of the map containing a point, which is the name of its points without J' and temperature
+    pub fn map_name(&self, point: &Point) -> String {
+        self.name_parts(point, None)[2..].join("_")
+    }
+
+    /// The rendering of the sections and maps
+    pub fn rendering(&self) -> Rendering {
+        let [low, high] = self.clamp_percentiles;
+        Rendering {
+            log: true,
+            clamp: (low as f64, high as f64),
+        }
+    }
+
+    fn name_parts(&self, point: &Point, skip: Option<Anneal>) -> Vec<String> {
+        let mut parts = Vec::new();
+        if skip != Some(Anneal::JPrime) {
+            parts.push(format!("j_{}", point.j_prime));
+        }
+        if skip != Some(Anneal::Temp) {
+            parts.push(format!("t_{}", point.temp));
+        }
+        if self.j_2.values().len() > 1 && skip != Some(Anneal::J2) {
+            parts.push(format!("j2_{}", point.j_2));
+        }
+        let further = [
+            (&self.j_3, point.j_3, "j3"),
+            (&self.j_4, point.j_4, "j4"),
+            (&self.j_5, point.j_5, "j5"),
+        ];
+        for (grid, j, name) in further {
+            if let (Some(grid), Some(j)) = (grid, j) {
+                if grid.values().len() > 1 {
+                    parts.push(format!("{}_{}", name, j));
+                }
+            }
+        }
+        if self.fill_frac.values().len() > 1 {
+            parts.push(format!("f_{}", point.fill_frac));
+        }
+        if self.size.values().len() > 1 {
+            let (a, b, c) = point.size;
+            parts.push(format!("s_{}x{}x{}", a, b, c));
+        }
+        parts
+    }
+
+    /// The seed of the chain containing the point
+    pub fn chain_seed(&self, point: &Point) -> Option<String> {
+        self.seed
+            .as_ref()
+            .map(|seed| format!("{}_{}", seed, self.chain_name(point)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_grids() {
+        let cases = [
+            ("1.5", Grid::Single(1.5)),
+            (" -2 ", Grid::Single(-2.0)),
+            ("0,0.5,2", Grid::List(vec![0.0, 0.5, 2.0])),
+            ("6:0:4", Grid::linear(6.0, 0.0, 4)),
+            ("0.1:10:3:log", Grid::log(0.1, 10.0, 3)),
+        ];
+        for (string, expected) in cases {
+            assert_eq!(string.parse::<Grid>(), Ok(expected), "{}", string);
+        }
+        for string in ["", "a", "1,b", "1:2", "1:2:x", "1:2:3:lin", "1:2:3:log:4"] {
+            assert!(string.parse::<Grid>().is_err(), "{}", string);
+        }
+    }
+
+    #[test]
+    fn values_of_grids() {
+        assert_eq!(Grid::Single(1.5).values(), [1.5]);
+        assert_eq!(Grid::List(vec![2.0, 1.0]).values(), [2.0, 1.0]);
+        assert_eq!(Grid::linear(6.0, 0.0, 4).values(), [6.0, 4.0, 2.0, 0.0]);
+        assert_eq!(Grid::linear(3.0, 5.0, 1).values(), [3.0]);
+        let log = Grid::log(0.1, 10.0, 3).values();
+        assert_eq!(log.len(), 3);
+        for (value, expected) in log.iter().zip([0.1, 1.0, 10.0]) {
+            assert!((value - expected).abs() < 1e-5 * expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn invalid_grids() {
+        for grid in [
+            Grid::Single(1.0),
+            Grid::linear(0.0, 1.0, 3),
+            Grid::log(0.1, 10.0, 3),
+        ] {
+            assert_eq!(grid.validate(), Ok(()), "{:?}", grid);
+        }
+        for grid in [
+            Grid::List(vec![]),
+            Grid::linear(0.0, 1.0, 0),
+            Grid::log(0.0, 10.0, 3),
+            Grid::log(-1.0, -10.0, 3),
+            Grid::List(vec![0.5, 1.0, 0.5]),
+            Grid::linear(1.0, 1.0, 2),
+        ] {
+            assert!(grid.validate().is_err(), "{:?}", grid);
+        }
+        let config = SweepConfig {
+            size: SizeGrid::Single((4, 4, 4)),
+            j_prime: Grid::log(0.0, 1.0, 4),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+        let config = SweepConfig {
+            size: SizeGrid::List(vec![(4, 4, 4), (4, 4, 4)]),
+            j_prime: Grid::Single(0.5),
+            ..config
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn grids_in_toml() {
+        let config: SweepConfig = toml::from_str(
+            "j_2 = 1.5
+j_prime = [0.0, 0.5]
+temp = { start = 6, end = 0, steps = 4 }
+fill_frac = { start = 0.25, end = 1, steps = 3, log = true }
+size = [[4, 4, 4], [6, 6, 8]]",
+        )
+        .unwrap();
+        assert_eq!(config.j_2, Grid::Single(1.5));
+        assert_eq!(config.j_prime, Grid::List(vec![0.0, 0.5]));
+        assert_eq!(config.temp, Grid::linear(6.0, 0.0, 4));
+        assert_eq!(config.fill_frac, Grid::log(0.25, 1.0, 3));
+        assert_eq!(config.size, SizeGrid::List(vec![(4, 4, 4), (6, 6, 8)]));
+        assert_eq!(config.points().len(), 2 * 3 * 2 * 4);
+    }
+
+    #[test]
+    fn further_shells() {
+        let config: SweepConfig = toml::from_str(
+            "size = [4, 4, 4]
+j_2 = 2
+j_prime = 0.5
+j_3 = [0.25, -0.25]
+j_4 = 0.125
+temp = 1",
+        )
+        .unwrap();
+        config.validate().unwrap();
+        let couplings: Vec<_> = config.points().iter().map(Point::couplings).collect();
+        assert_eq!(
+            couplings,
+            [vec![1.0, 2.0, 0.25, 0.125], vec![1.0, 2.0, -0.25, 0.125]]
+        );
+        let names: Vec<_> = config
+            .points()
+            .iter()
+            .map(|p| config.point_name(p))
+            .collect();
+        assert_eq!(names, ["j_0.5_t_1_j3_0.25", "j_0.5_t_1_j3_-0.25"]);
+
+        let skipped = SweepConfig {
+            j_3: None,
+            ..config
+        };
+        assert!(skipped.validate().is_err());
+    }
+}
@@ -0,0 +1,178 @@
+use rustfft::{num_complex::Complex, FftDirection};
+
+use crate::scattering::fft_3d;
+use crate::{Array3d, YellVolume};
+
+/// The three dimensional difference pair distribution function, the Fourier transform
+/// of the diffuse scattering without Bragg peaks. It is positive at the vectors u
+/// where the deviations from the average structure are correlated
+/// and negative where they are anticorrelated, weighted with the scattering of the ions.
+/// Like the 3D-ΔPDF of a measurement it is calculated from the intensities up to the largest hkl,
+/// so it has the same resolution as the experimental one.
+#[derive(Clone, Debug)]
+pub struct DeltaPdf {
+    /// The pdf, the first index along every axis corresponds to -max
+    pdf: Array3d<f64>,
+    /// The largest index of u along a, b and c
+    max: (usize, usize, usize),
+}
+
+impl DeltaPdf {
+    /// Calculates the pdf of intensities given between -max and max on the reciprocal lattice
+    /// of the model, with the first index along every axis corresponding to -max.
+    /// There are as many vectors u as hkl, the pdf is the mean over hkl of I(hkl) cos(2 pi hkl.u)
+    pub fn new(intensities: &Array3d<f64>, max: (usize, usize, usize)) -> Self {
+        let (width, height, depth) = intensities.dims();
+        assert_eq!(
+            (width, height, depth),
+            (2 * max.0 + 1, 2 * max.1 + 1, 2 * max.2 + 1)
+        );
+        // the transform is periodic and the indexing of the array wraps around,
+        // so hkl and u are stored at their index modulo the length
+        let shifted = |(i, j, k): (isize, isize, isize)| {
+            (i - max.0 as isize, j - max.1 as isize, k - max.2 as isize)
+        };
+        let mut transform = Array3d::<Complex<f64>>::new(width, height, depth);
+        for i in 0..width as isize {
+            for j in 0..height as isize {
+                for k in 0..depth as isize {
+                    transform[shifted((i, j, k))] = Complex::new(intensities[(i, j, k)], 0.0);
+                }
+            }
+        }
+        fft_3d(&mut transform, FftDirection::Inverse);
+        let points = (width * height * depth) as f64;
+        let mut pdf = Array3d::new(width, height, depth);
+        for i in 0..width as isize {
+            for j in 0..height as isize {
+                for k in 0..depth as isize {
+                    // the intensities are centrosymmetric, so the transform is real
+                    pdf[(i, j, k)] = transform[shifted((i, j, k))].re / points;
+                }
+            }
+        }
+        Self { pdf, max }
+    }
+
+    /// Getter function for the pdf, the first index along every axis corresponds to -max
+    pub fn pdf(&self) -> &Array3d<f64> {
+        &self.pdf
+    }
+
+    /// Getter function for the largest index of u along a, b and c
+    pub fn max(&self) -> (usize, usize, usize) {
+        self.max
+    }
+
+    /// The pdf as a Yell volume in direct space, where u is given in fractions of the average
+    /// unit cell, of which there are the given number of supercells along a, b and c in the model.
+    /// The step of u is the number of unit cells over the number of hkl,
+    /// so the pdf covers the vectors up to half of the model
+    pub fn to_yell(
+        &self,
+        supercells: (usize, usize, usize),
+        unit_cell: [f64; 6],
+    ) -> YellVolume<'_> {
+        let (max_a, max_b, max_c) = self.max;
+        let (a, b, c) = supercells;
+        let step = |cells: usize, max: usize| cells as f64 / (2 * max + 1) as f64;
+        let steps = [step(a, max_a), step(b, max_b), step(c, max_c)];
+        YellVolume {
+            data: &self.pdf,
+            lower_limits: [
+                -(max_a as f64) * steps[0],
+                -(max_b as f64) * steps[1],
+                -(max_c as f64) * steps[2],
+            ],
+            step_sizes: steps,
+            unit_cell,
+            is_direct: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scattering::tests::{direct_amplitude, occupations};
+    use crate::{Model, PairHamiltonian};
+    use std::f64::consts::PI;
+
+    #[test]
+    fn fft_equals_direct_sum() {
+        let model = Model::new((4, 4, 6), vec![-1.0, 0.5], 0.5, Some("delta pdf")).unwrap();
+        let delta_pdf = model.difference_scattering(1.0).delta_pdf();
+        let max = delta_pdf.max();
+        assert_eq!(max, (2, 2, 3));
+
+        // the deviations of the occupations from their average at the same position in the unit cell
+        let mut deviations = occupations(&model.grid, &Model::<PairHamiltonian>::naming());
+        let (width, height, depth) = model.dims();
+        let cells = (width * height * depth / 8) as f64;
+        let position = |x: usize, y: usize, z: usize| (x % 2) + 2 * (y % 2) + 4 * (z % 2);
+        for (_, occupations) in &mut deviations {
+            let mut average = [0.0; 8];
+            for x in 0..width {
+                for y in 0..height {
+                    for z in 0..depth {
+                        average[position(x, y, z)] +=
+                            occupations[(x as isize, y as isize, z as isize)] / cells;
+                    }
+                }
+            }
+            for x in 0..width {
+                for y in 0..height {
+                    for z in 0..depth {
+                        occupations[(x as isize, y as isize, z as isize)] -=
+                            average[position(x, y, z)];
+                    }
+                }
+            }
+        }
+
+        let range = |max: usize| -(max as isize)..=max as isize;
+        let mut intensities = Vec::new();
+        for h in range(max.0) {
+            for k in range(max.1) {
+                for l in range(max.2) {
+                    let amplitude = direct_amplitude(&deviations, model.cell(), (h, k, l));
+                    intensities.push(((h, k, l), amplitude.norm_sqr()));
+                }
+            }
+        }
+        let lengths = [2 * max.0 + 1, 2 * max.1 + 1, 2 * max.2 + 1].map(|len| len as f64);
+        let largest = intensities
+            .iter()
+            .fold(0.0, |largest: f64, (_, intensity)| largest.max(*intensity));
+        for u in range(max.0) {
+            for v in range(max.1) {
+                for w in range(max.2) {
+                    let direct = intensities
+                        .iter()
+                        .map(|((h, k, l), intensity)| {
+                            let phase = 2.0
+                                * PI
+                                * (*h as f64 * u as f64 / lengths[0]
+                                    + *k as f64 * v as f64 / lengths[1]
+                                    + *l as f64 * w as f64 / lengths[2]);
+                            intensity * phase.cos()
+                        })
+                        .sum::<f64>()
+                        / intensities.len() as f64;
+                    let index = (u + max.0 as isize, v + max.1 as isize, w + max.2 as isize);
+                    let fft = delta_pdf.pdf()[index];
+                    assert!(
+                        (fft - direct).abs() <= 1e-9 * largest,
+                        "{} != {} at {:?}",
+                        fft,
+                        direct,
+                        (u, v, w)
+                    );
+                }
+            }
+        }
+        // without Bragg peaks there is no intensity at the origin of reciprocal space
+        let origin = intensities.len() / 2;
+        assert_eq!(intensities[origin].0, (0, 0, 0));
+        assert!(intensities[origin].1 < 1e-12 * largest);
+    }
+}
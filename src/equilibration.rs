@@ -0,0 +1,133 @@
+/// The number of values averaged into one batch of the MSER-5 rule
+const BATCH: usize = 5;
+
+/// Decides when a time series, e.g. the energy after every sweep, has become stationary.
+/// It uses the MSER-5 rule (White 1997): the truncation point is the one that minimises
+/// the squared standard error of the mean of the remaining batch means.
+/// The series is considered stationary once this point lies in its first half,
+/// otherwise the drift has not died out yet.
+#[derive(Clone, Debug, Default)]
+pub struct EquilibrationDetector {
+    series: Vec<f64>,
+    min_len: usize,
+}
+
+impl EquilibrationDetector {
+    /// min_len is the number of values that is needed before the series can be stationary
+    pub fn new(min_len: usize) -> Self {
+        Self {
+            series: Vec::new(),
+            min_len,
+        }
+    }
+
+    pub fn add_value(&mut self, x: f64) {
+        self.series.push(x)
+    }
+
+    /// The number of values in the series
+    pub fn len(&self) -> usize {
+        self.series.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.series.is_empty()
+    }
+
+    /// The MSER-5 truncation point as the number of values to discard
+    pub fn truncation(&self) -> usize {
+        mser_truncation(&self.series, BATCH)
+    }
+
+    /// Checks if the series is long enough and its truncation point is in the first half
+    pub fn is_equilibrated(&self) -> bool {
+        self.len() >= self.min_len.max(4 * BATCH) && 2 * self.truncation() <= self.len()
+    }
+}
+
+/// The number of values to discard from the start of the series
+/// following the MSER rule with the given batch size.
+/// At least 2 batches are kept, so the statistic does not vanish trivially at the end
+pub fn mser_truncation(series: &[f64], batch: usize) -> usize {
+    let means: Vec<f64> = series
+        .chunks_exact(batch)
+        .map(|chunk| chunk.iter().sum::<f64>() / batch as f64)
+        .collect();
+    if means.len() < 3 {
+        return 0;
+    }
+    // sums over the tails of the batch means allow evaluating every truncation in O(1)
+    let mut sum = 0.0;
+    let mut sum_sq = 0.0;
+    let mut best = (f64::INFINITY, 0);
+    for (d, mean) in means.iter().enumerate().rev() {
+        sum += mean;
+        sum_sq += mean * mean;
+        let n = (means.len() - d) as f64;
+        if n < 2.0 {
+            continue;
+        }
+        let squares = (sum_sq - sum * sum / n).max(0.0);
+        let statistic = squares / (n * n);
+        if statistic <= best.0 {
+            best = (statistic, d);
+        }
+    }
+    best.1 * batch
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha12Rng;
+
+    /// A detector with the values t -> signal(t) + uniform noise in [-1, 1)
+    fn detector(len: usize, min_len: usize, signal: impl Fn(f64) -> f64) -> EquilibrationDetector {
+        let mut rng = ChaCha12Rng::seed_from_u64(15);
+        let mut detector = EquilibrationDetector::new(min_len);
+        for t in 0..len {
+            detector.add_value(signal(t as f64) + rng.gen_range(-1.0..1.0));
+        }
+        detector
+    }
+
+    #[test]
+    fn truncates_the_transient() {
+        // the transient is below the noise after about 60 values
+        let relaxing = detector(500, 50, |t| 20.0 * (-t / 20.0).exp());
+        let truncation = relaxing.truncation();
+        assert!((20..=150).contains(&truncation), "{}", truncation);
+        assert_eq!(truncation % BATCH, 0);
+        assert!(relaxing.is_equilibrated());
+
+        assert_eq!(mser_truncation(&[3.0; 100], BATCH), 0);
+    }
+
+    #[test]
+    fn drift_is_not_stationary() {
+        let drifting = detector(500, 50, |t| 0.05 * t);
+        assert!(2 * drifting.truncation() > drifting.len());
+        assert!(!drifting.is_equilibrated());
+    }
+
+    #[test]
+    fn needs_the_minimal_length() {
+        let mut constant = EquilibrationDetector::new(50);
+        for _ in 0..49 {
+            constant.add_value(1.0);
+        }
+        assert!(!constant.is_equilibrated());
+        constant.add_value(1.0);
+        assert!(constant.is_equilibrated());
+
+        // and at least 4 batches
+        let mut short = EquilibrationDetector::new(0);
+        for _ in 0..4 * BATCH - 1 {
+            short.add_value(1.0);
+        }
+        assert!(!short.is_equilibrated());
+        short.add_value(1.0);
+        assert!(short.is_equilibrated());
+    }
+}
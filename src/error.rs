@@ -0,0 +1,119 @@
+use std::fmt::Display;
+
+use crate::Index;
+
+/// The errors that can occur while reading models and related files
+#[derive(Debug)]
+pub enum ModelError {
+    /// The file could not be read
+    Io(std::io::Error),
+    /// The file ended before the field was read
+    MissingField { line: usize, field: &'static str },
+    /// The field could not be parsed
+    InvalidField {
+        line: usize,
+        field: &'static str,
+        value: String,
+    },
+    /// The sides of the grid are not all even
+    OddDims { dims: (usize, usize, usize) },
+    /// The number of values does not match the size of the model
+    SizeMismatch {
+        field: &'static str,
+        expected: usize,
+        found: usize,
+    },
+    /// A cell contains a value that is not allowed on its sublattice
+    InvalidCell { index: Index, value: i8 },
+    /// The file does not start with the magic bytes of the binary format
+    BadMagic,
+    /// The version of the binary format is newer than this crate supports
+    UnsupportedVersion { found: u16, supported: u16 },
+    /// The checksum stored in the file does not match its content
+    ChecksumMismatch { expected: u64, found: u64 },
+    /// The model has more neighbour shells than the hamiltonian supports
+    TooManyShells { found: usize, supported: usize },
+    /// The vectors of a neighbour shell do not fit into half of the grid
+    ShellTooLarge {
+        shell: usize,
+        dims: (usize, usize, usize),
+    },
+}
+
+impl Display for ModelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModelError::Io(err) => write!(f, "{}", err),
+            ModelError::MissingField { line, field } => {
+                write!(f, "line {}: expected {} but the file ended", line, field)
+            }
+            ModelError::InvalidField { line, field, value } => {
+                write!(
+                    f,
+                    "line {}: could not parse {} from '{}'",
+                    line, field, value
+                )
+            }
+            ModelError::OddDims { dims } => {
+                write!(f, "the sides of the grid {:?} need to be even", dims)
+            }
+            ModelError::SizeMismatch {
+                field,
+                expected,
+                found,
+            } => write!(
+                f,
+                "expected {} values for {} but found {}",
+                expected, field, found
+            ),
+            ModelError::InvalidCell { index, value } => {
+                let allowed = if (index.0 + index.1 + index.2) % 2 == 0 {
+                    "0 on a metal site"
+                } else {
+                    "-1 or 1 on a cyanometalate site"
+                };
+                write!(
+                    f,
+                    "invalid value {} at {:?}, expected {}",
+                    value, index, allowed
+                )
+            }
+            ModelError::BadMagic => write!(f, "the file is not a binary model"),
+            ModelError::UnsupportedVersion { found, supported } => write!(
+                f,
+                "version {} of the binary format is not supported, the latest is {}",
+                found, supported
+            ),
+            ModelError::ChecksumMismatch { expected, found } => write!(
+                f,
+                "the checksum {:016x} does not match the content {:016x}",
+                expected, found
+            ),
+            ModelError::TooManyShells { found, supported } => write!(
+                f,
+                "the model has {} shells but at most {} are supported",
+                found, supported
+            ),
+            ModelError::ShellTooLarge { shell, dims } => write!(
+                f,
+                "the vectors of shell {} do not fit into half of the grid {:?}",
+                shell, dims
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ModelError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ModelError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ModelError {
+    fn from(err: std::io::Error) -> Self {
+        ModelError::Io(err)
+    }
+}
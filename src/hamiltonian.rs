@@ -0,0 +1,228 @@
+use std::fmt::Debug;
+
+use crate::{cyanometalate_sites, shells, Array3d, Index, ModelError, Shell, MAX_SHELLS};
+
+/// An energy model the Monte Carlo sampler can run on.
+/// The hamiltonian keeps track of its own bookkeeping, such that the total energy
+/// is known at all times and energy differences only require local information.
+pub trait Hamiltonian: Debug {
+    /// The local contributions around a set of sites.
+    /// The difference between the local contributions before and after a move
+    /// gives the change of the energy.
+    type Local;
+
+    /// Checks that the hamiltonian can be used on a grid with the given dims
+    fn check_dims(&self, _dims: (usize, usize, usize)) -> Result<(), ModelError> {
+        Ok(())
+    }
+
+    /// Recalculates the bookkeeping from the whole grid
+    fn calc_sums(&mut self, grid: &Array3d<i8>);
+
+    /// The local contributions of the given sites.
+    /// Terms between two of the given sites may be counted more than once,
+    /// so this is only correct for moves that don't change them (like swaps).
+    fn local(&self, grid: &Array3d<i8>, sites: &[Index]) -> Self::Local;
+
+    /// The change of the energy if the local contributions change from old to new
+    fn delta_energy(&self, old: &Self::Local, new: &Self::Local) -> f32;
+
+    /// Updates the bookkeeping after a move has been accepted
+    fn accept(&mut self, old: &Self::Local, new: &Self::Local);
+
+    /// The total energy
+    fn energy(&self) -> f32;
+
+    /// The named contributions of all terms to the total energy
+    fn contributions(&self) -> Vec<(String, f32)>;
+}
+
+/// A pair interaction between the cyanometalates, where every neighbour shell
+/// has its own interaction energy
+#[derive(Clone, Debug)]
+pub struct PairHamiltonian {
+    /// The neighbour shells considered in the hamiltonian
+    shells: Vec<Shell>,
+    /// The interaction energy of every shell
+    couplings: Vec<f32>,
+    /// The sum over all pairs of neighbours in every shell
+    sums: Vec<i64>,
+}
+
+impl PairHamiltonian {
+    /// Constructor for the PairHamiltonian.
+    /// couplings are the interaction energies of the neighbour shells,
+    /// starting with the nearest neighbours, there can be at most MAX_SHELLS of them.
+    pub fn new(couplings: Vec<f32>) -> Self {
+        assert!(
+            couplings.len() <= MAX_SHELLS,
+            "at most {} shells are supported",
+            MAX_SHELLS
+        );
+        Self {
+            shells: shells::neighbour_shells(couplings.len()),
+            sums: vec![0; couplings.len()],
+            couplings,
+        }
+    }
+
+    /// Getter function for the interaction energies of the shells
+    pub fn couplings(&self) -> &[f32] {
+        &self.couplings
+    }
+
+    /// Getter function for the neighbour shells
+    pub fn shells(&self) -> &[Shell] {
+        &self.shells
+    }
+
+    /// Getter function for the neighbour sums of the shells
+    pub fn neighbour_sums(&self) -> &[i64] {
+        &self.sums
+    }
+
+    /// The sum over all neighbours to idx in the given shell
+    #[inline]
+    fn shell_sum_from(grid: &Array3d<i8>, shell: &Shell, idx: Index) -> i64 {
+        let (i, j, k) = idx;
+        let mut sum = 0;
+        for (di, dj, dk) in shell.vectors() {
+            sum += grid[(i + di, j + dj, k + dk)] as i64;
+        }
+        grid[idx] as i64 * sum
+    }
+
+    /// The energy corresponding to the given neighbour sums
+    fn energy_of(&self, sums: &[i64]) -> f32 {
+        self.couplings
+            .iter()
+            .zip(sums)
+            .map(|(j, sum)| j * *sum as f32)
+            .sum()
+    }
+}
+
+impl Hamiltonian for PairHamiltonian {
+    /// The neighbour sums of every shell around the sites,
+    /// the entries after the last shell stay 0
+    type Local = [i64; MAX_SHELLS];
+
+    fn check_dims(&self, dims: (usize, usize, usize)) -> Result<(), ModelError> {
+        match self.shells.iter().position(|shell| !shell.fits_into(dims)) {
+            Some(n) => Err(ModelError::ShellTooLarge { shell: n + 1, dims }),
+            None => Ok(()),
+        }
+    }
+
+    fn calc_sums(&mut self, grid: &Array3d<i8>) {
+        let mut sums = vec![0; self.shells.len()];
+        for idx in cyanometalate_sites(grid.dims()) {
+            for (sum, shell) in sums.iter_mut().zip(&self.shells) {
+                *sum += Self::shell_sum_from(grid, shell, idx);
+            }
+        }
+        // every pair was counted from both sides
+        self.sums = sums.into_iter().map(|sum| sum / 2).collect();
+    }
+
+    fn local(&self, grid: &Array3d<i8>, sites: &[Index]) -> Self::Local {
+        let mut sums = [0; MAX_SHELLS];
+        for (sum, shell) in sums.iter_mut().zip(&self.shells) {
+            *sum = sites
+                .iter()
+                .map(|idx| Self::shell_sum_from(grid, shell, *idx))
+                .sum();
+        }
+        sums
+    }
+
+    fn delta_energy(&self, old: &Self::Local, new: &Self::Local) -> f32 {
+        self.energy_of(new) - self.energy_of(old)
+    }
+
+    fn accept(&mut self, old: &Self::Local, new: &Self::Local) {
+        for ((sum, old), new) in self.sums.iter_mut().zip(old).zip(new) {
+            *sum += new - old;
+        }
+    }
+
+    fn energy(&self) -> f32 {
+        self.energy_of(&self.sums)
+    }
+
+    fn contributions(&self) -> Vec<(String, f32)> {
+        self.couplings
+            .iter()
+            .zip(&self.sums)
+            .enumerate()
+            .map(|(n, (j, sum))| (format!("j_{}", n + 1), j * *sum as f32))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Model;
+
+    /// The nearest and next nearest neighbour sums as the model counted them
+    /// before the shells were introduced, from the pairs around every metal site
+    fn baseline_sums(grid: &Array3d<i8>) -> (i64, i64) {
+        let (a, b, c) = grid.dims();
+        let mut nearest = 0;
+        let mut next_nearest = 0;
+        for i in 0..a as isize {
+            for j in 0..b as isize {
+                for k in 0..c as isize {
+                    if (i + j + k) % 2 != 0 {
+                        continue;
+                    }
+                    let g = |di, dj, dk| grid[(i + di, j + dj, k + dk)] as i64;
+                    nearest += g(0, 1, 0) * g(0, 0, 1)
+                        + g(0, 0, -1) * g(0, 1, 0)
+                        + g(1, 0, 0) * g(0, 0, 1)
+                        + g(0, 0, -1) * g(1, 0, 0)
+                        + g(1, 0, 0) * g(0, 1, 0)
+                        + g(0, -1, 0) * g(1, 0, 0);
+                    next_nearest += g(-1, 0, 0) * g(1, 0, 0)
+                        + g(0, -1, 0) * g(0, 1, 0)
+                        + g(0, 0, -1) * g(0, 0, 1);
+                }
+            }
+        }
+        (nearest, next_nearest)
+    }
+
+    #[test]
+    fn two_shells_reproduce_the_baseline() {
+        let (j_1, j_2) = (-0.75, 1.5);
+        let mut model = Model::new((6, 8, 10), vec![j_1, j_2], 0.4, Some("baseline")).unwrap();
+        for step in 0..2000 {
+            if step % 500 == 0 {
+                let (nearest, next_nearest) = baseline_sums(&model.grid);
+                assert_eq!(
+                    model.hamiltonian().neighbour_sums(),
+                    [nearest, next_nearest]
+                );
+                let energy = j_1 * nearest as f32 + j_2 * next_nearest as f32;
+                assert!((model.get_hamiltonian() - energy).abs() < 1e-3);
+            }
+            model.monte_carlo_step(1.0);
+        }
+    }
+
+    #[test]
+    fn shells_need_to_fit_into_half_the_grid() {
+        // the fifth shell contains (3, 1, 0)
+        let couplings = vec![1.0; 5];
+        assert!(Model::new((6, 6, 6), couplings.clone(), 0.5, None).is_ok());
+        assert!(matches!(
+            Model::new((4, 6, 6), couplings, 0.5, None),
+            Err(ModelError::ShellTooLarge { shell: 5, .. })
+        ));
+        let mut model = Model::new((4, 4, 4), vec![1.0; 4], 0.5, None).unwrap();
+        assert!(model
+            .set_hamiltonian(PairHamiltonian::new(vec![1.0; 5]))
+            .is_err());
+    }
+}
@@ -1,5 +1,5 @@
 use rand::prelude::*;
-use rand::rngs::StdRng;
+use rand_chacha::ChaCha12Rng;
 use rand_seeder::Seeder;
 use std::collections::HashMap;
 use std::fmt::Debug;
@@ -8,37 +8,82 @@ use std::path::Path;
 use std::str::FromStr;
 
 mod array3d;
-use array3d::Array3d;
+pub use array3d::Array3d;
 mod mmcif;
 pub use mmcif::Ion;
 mod stats;
 pub use stats::StreamingStats;
 mod logs;
 pub use logs::CsvLogger;
-
-type Index = (isize, isize, isize);
+mod shells;
+pub use shells::{Shell, MAX_SHELLS};
+mod hamiltonian;
+pub use hamiltonian::{Hamiltonian, PairHamiltonian};
+mod error;
+pub use error::ModelError;
+mod binary;
+pub use binary::upgrade_txt;
+mod config;
+pub use config::{Anneal, Grid, Point, SizeGrid, SweepConfig};
+mod manifest;
+pub use manifest::{Chain, Failure, Manifest};
+mod equilibration;
+pub use equilibration::{mser_truncation, EquilibrationDetector};
+mod sweep;
+pub use sweep::SweepRunner;
+mod checkpoint;
+pub use checkpoint::{Checkpoint, TemperingCheckpoint};
+mod trajectory;
+pub use trajectory::{OrderParameter, Trajectory};
+mod tempering;
+pub use tempering::ParallelTempering;
+mod wang_landau;
+pub use wang_landau::{DensityOfStates, Thermodynamics, WangLandau};
+mod reweighting;
+pub use reweighting::{ferrenberg_swendsen, NeighbourHistogram};
+mod short_range_order;
+pub use short_range_order::ShortRangeOrder;
+mod scattering;
+pub use scattering::{difference_scattering, diffuse_scattering, DiffuseScattering};
+mod delta_pdf;
+pub use delta_pdf::DeltaPdf;
+mod yell;
+pub use yell::YellVolume;
+mod section;
+pub use section::{Plane, Rendering, Section};
+
+pub type Index = (isize, isize, isize);
+
+/// All indexes of cyanometalate sites in a grid with the given dims
+pub fn cyanometalate_sites(dims: (usize, usize, usize)) -> impl Iterator<Item = Index> {
+    let (a, b, c) = dims;
+    (0..a as isize).flat_map(move |i| {
+        (0..b as isize).flat_map(move |j| {
+            (0..(c / 2) as isize).map(move |k| (i, j, 2 * k + i % 2 + j % 2 + 1))
+        })
+    })
+}
 
 const DIST_MN_MN: f32 = 10.0003;
 const CO_C: f32 = 1.89;
 const CO_N: f32 = 3.03;
 
 #[derive(Debug)]
-pub struct Model<const S: usize> {
+pub struct Model<H: Hamiltonian = PairHamiltonian> {
+    /// The number of grid points along a, b and c.
+    /// These are twice the number of supercells in each direction.
+    dims: (usize, usize, usize),
     /// The grid where the Ions are stored.
     /// 0 corresponds to the fixed metal ion
     /// 1 to the cyanometalate
     /// -1 to the vacancy at a cyanometalate site
-    grid: Array3d<i8, S, S, S>,
-    /// The interaction energy of nearest neighbours
-    j_1: f32,
-    /// sum over the nearest neighbours
-    nearest_neighbours: i64,
-    /// The interaction energy of nect nearest neighbours
-    j_2: f32,
-    /// sum over the nearest neighbours
-    next_nearest_neighbours: i64,
+    grid: Array3d<i8>,
+    /// The energy model
+    hamiltonian: H,
+    /// The number of cyanometalates in the grid
+    metalates: usize,
     /// The random number generator
-    rng: StdRng,
+    rng: ChaCha12Rng,
     /// The number of moves where the difference in energy was negative
     good_moves: u32,
     /// The number of accepted moves with the difference in energy >= 0
@@ -47,49 +92,70 @@ pub struct Model<const S: usize> {
     rejected_moves: u32,
 }
 
-impl<const S: usize> Model<S> {
-    /// Constructor for the Model
-    pub fn new(j_1: f32, j_2: f32, fill_frac: f32, seed: Option<&'static str>) -> Self {
-        assert!(S % 2 == 0, "grid need to have side length 2*N");
+impl Model<PairHamiltonian> {
+    /// Constructor for the Model with pair interactions
+    /// dims are the number of grid points along a, b and c, each of them needs to be even.
+    /// couplings are the interaction energies of the neighbour shells,
+    /// starting with the nearest neighbours, all of their vectors need to fit into half the grid.
+    pub fn new(
+        dims: (usize, usize, usize),
+        couplings: Vec<f32>,
+        fill_frac: f32,
+        seed: Option<&str>,
+    ) -> Result<Self, ModelError> {
+        Self::with_hamiltonian(dims, PairHamiltonian::new(couplings), fill_frac, seed)
+    }
+}
+
+impl<H: Hamiltonian> Model<H> {
+    /// Constructor for the Model with an arbitrary energy model
+    /// dims are the number of grid points along a, b and c, each of them needs to be even.
+    pub fn with_hamiltonian(
+        dims: (usize, usize, usize),
+        hamiltonian: H,
+        fill_frac: f32,
+        seed: Option<&str>,
+    ) -> Result<Self, ModelError> {
+        let (a, b, c) = dims;
+        if !(a.is_multiple_of(2) && b.is_multiple_of(2) && c.is_multiple_of(2)) {
+            return Err(ModelError::OddDims { dims });
+        }
+        hamiltonian.check_dims(dims)?;
         let mut rng = if let Some(seed) = seed {
             Seeder::from(seed).make_rng()
         } else {
-            StdRng::from_entropy()
+            ChaCha12Rng::from_entropy()
         };
 
         let mut is_ok = false;
 
-        let metalates = (fill_frac * (S * S * S / 2) as f32).floor() as usize;
+        let sites = a * b * c / 2;
+        let metalates = (fill_frac * sites as f32).floor() as usize;
 
         let mut shuffle = Vec::<i8>::new();
         for _ in 0..metalates {
             is_ok = true;
             shuffle.push(1)
         }
-        for _ in metalates..S * S * S / 2 {
-            is_ok = is_ok && true;
+        for _ in metalates..sites {
             shuffle.push(-1)
         }
         shuffle.shuffle(&mut rng);
         let mut shuffle = shuffle.into_iter();
 
-        let mut grid = Array3d::<i8, S, S, S>::new();
+        let mut grid = Array3d::<i8>::new(a, b, c);
 
-        for i in 0..(S as isize) {
-            for j in 0..(S as isize) {
-                for k in 0..((S / 2) as isize) {
-                    let idx = (i, j, 2 * k + i % 2 + j % 2 + 1);
-                    grid[idx] = shuffle.next().expect("should not fail because of the way the indexes work and S is divisible by 2");
-                }
-            }
+        for idx in cyanometalate_sites(dims) {
+            grid[idx] = shuffle.next().expect(
+                "should not fail because of the way the indexes work and all sides are divisible by 2",
+            );
         }
 
         let mut out = Self {
+            dims,
             grid,
-            j_1,
-            nearest_neighbours: 0,
-            j_2,
-            next_nearest_neighbours: 0,
+            hamiltonian,
+            metalates,
             good_moves: 0,
             bad_moves: 0,
             rejected_moves: 0,
@@ -97,134 +163,100 @@ impl<const S: usize> Model<S> {
         };
         out.calc_sums();
         assert!(is_ok, "The fill fraction of the start was zero or one!");
-        out
+        Ok(out)
     }
 
-    /// Updates the nearest neighbour and next nearest neighbour sums
+    /// Recalculates the bookkeeping of the hamiltonian from the grid
     pub fn calc_sums(&mut self) {
-        self.nearest_neighbours = 0;
-        self.next_nearest_neighbours = 0;
-        for i in 0..(S as isize) {
-            for j in 0..(S as isize) {
-                for k in 0..((S / 2) as isize) {
-                    let idx = (i, j, 2 * k + i % 2 + j % 2);
-                    self.nearest_neighbours += self.diags_around(idx) as i64;
-                    self.next_nearest_neighbours += self.axis_through(idx) as i64;
-                }
-            }
-        }
-    }
-}
-
-impl<const S: usize> Model<S> {
-    /// The sum over the nearest neigbours around a metal ion.
-    /// To remove over counting in each plane the first of the two indexes
-    /// is only allowed to increase or stay the same
-    #[inline]
-    fn diags_around(&self, idx: Index) -> i8 {
-        let (i, j, k) = idx;
-        // next nearest neighbours in the jk plane
-        let mut sum = self.grid[(i, j + 1, k)] * self.grid[(i, j, k + 1)]
-            + self.grid[(i, j, k - 1)] * self.grid[(i, j + 1, k)];
-
-        // next nearest neighbours in the ik plane
-        sum += self.grid[(i + 1, j, k)] * self.grid[(i, j, k + 1)]
-            + self.grid[(i, j, k - 1)] * self.grid[(i + 1, j, k)];
-
-        // next nearest neighbours in the ij plane
-        sum += self.grid[(i + 1, j, k)] * self.grid[(i, j + 1, k)]
-            + self.grid[(i, j - 1, k)] * self.grid[(i + 1, j, k)];
-        sum
-    }
-
-    /// The sum over the next nearest neighbours around a metal ion.
-    #[inline]
-    fn axis_through(&self, idx: Index) -> i8 {
-        let (i, j, k) = idx;
-        self.grid[(i - 1, j, k)] * self.grid[(i + 1, j, k)]
-            + self.grid[(i, j - 1, k)] * self.grid[(i, j + 1, k)]
-            + self.grid[(i, j, k - 1)] * self.grid[(i, j, k + 1)]
-    }
-
-    /// The sum over all nearest neighbours to idx
-    #[inline]
-    fn diags_from(&self, idx: Index) -> i8 {
-        let (i, j, k) = idx;
-        let mut sum = self.grid[(i, j, k)] * self.grid[(i, j + 1, k + 1)]
-            + self.grid[(i, j, k)] * self.grid[(i, j + 1, k - 1)]
-            + self.grid[(i, j, k)] * self.grid[(i, j - 1, k - 1)]
-            + self.grid[(i, j, k)] * self.grid[(i, j - 1, k + 1)];
-        sum += self.grid[(i, j, k)] * self.grid[(i + 1, j, k + 1)]
-            + self.grid[(i, j, k)] * self.grid[(i + 1, j, k - 1)]
-            + self.grid[(i, j, k)] * self.grid[(i - 1, j, k - 1)]
-            + self.grid[(i, j, k)] * self.grid[(i - 1, j, k + 1)];
-        sum += self.grid[(i, j, k)] * self.grid[(i + 1, j + 1, k)]
-            + self.grid[(i, j, k)] * self.grid[(i + 1, j - 1, k)]
-            + self.grid[(i, j, k)] * self.grid[(i - 1, j - 1, k)]
-            + self.grid[(i, j, k)] * self.grid[(i - 1, j + 1, k)];
-        sum
-    }
-
-    /// The sum over all nest neares neighbours to idx
-    #[inline]
-    fn axis_from(&self, idx: Index) -> i8 {
-        let (i, j, k) = idx;
-        self.grid[(i, j, k)] * self.grid[(i + 2, j, k)]
-            + self.grid[(i, j, k)] * self.grid[(i - 2, j, k)]
-            + self.grid[(i, j, k)] * self.grid[(i, j + 2, k)]
-            + self.grid[(i, j, k)] * self.grid[(i, j - 2, k)]
-            + self.grid[(i, j, k)] * self.grid[(i, j, k + 2)]
-            + self.grid[(i, j, k)] * self.grid[(i, j, k - 2)]
+        self.hamiltonian.calc_sums(&self.grid);
     }
 }
 
-impl<const S: usize> Model<S> {
+impl<H: Hamiltonian> Model<H> {
     /// Chooses an index to a cyanometalate uniformly
     fn uniform_idx(&mut self) -> Index {
-        let i = self.rng.gen_range(0..S as isize);
-        let j = self.rng.gen_range(0..S as isize);
-        let k = 2 * self.rng.gen_range(0..(S / 2) as isize) + i % 2 + j % 2 + 1;
+        let (a, b, c) = self.dims;
+        let i = self.rng.gen_range(0..a as isize);
+        let j = self.rng.gen_range(0..b as isize);
+        let k = 2 * self.rng.gen_range(0..(c / 2) as isize) + i % 2 + j % 2 + 1;
         (i, j, k)
     }
 
     /// Chooses two indexes to cyanometalates that don't have the same state
-    /// by the rejection acceptance method
-    fn choose_swap_pos(&mut self) -> (Index, Index) {
+    /// by the rejection acceptance method.
+    /// There are none if the lattice is empty or full, which grand canonical steps can reach
+    fn choose_swap_pos(&mut self) -> Option<(Index, Index)> {
+        if self.metalates == 0 || self.metalates == self.sites() {
+            return None;
+        }
         let idx_1 = self.uniform_idx();
         let mut idx_2 = self.uniform_idx();
         while self.grid[idx_1] == self.grid[idx_2] {
             idx_2 = self.uniform_idx()
         }
-        (idx_1, idx_2)
+        Some((idx_1, idx_2))
     }
 
-    /// Performs a Monte Carlo step.
+    /// Performs a Monte Carlo step, which does nothing if the lattice is empty or full.
     /// Note that $\beta = \frac{1}{T}$
     pub fn monte_carlo_step(&mut self, beta: f32) {
-        let (idx_1, idx_2) = self.choose_swap_pos();
-        let old_n_neighbours = self.diags_from(idx_1) + self.diags_from(idx_2);
-        let old_n_n_neighbours = self.axis_from(idx_1) + self.axis_from(idx_2);
-        let old_energy = self.j_1 * old_n_neighbours as f32 + self.j_2 * old_n_n_neighbours as f32;
+        let Some((idx_1, idx_2)) = self.choose_swap_pos() else {
+            return;
+        };
+        let old = self.hamiltonian.local(&self.grid, &[idx_1, idx_2]);
 
         self.swap(idx_1, idx_2);
 
-        let new_n_neighbours = self.diags_from(idx_1) + self.diags_from(idx_2);
-        let new_n_n_neighbours = self.axis_from(idx_1) + self.axis_from(idx_2);
-        let new_energy = self.j_1 * new_n_neighbours as f32 + self.j_2 * new_n_n_neighbours as f32;
+        let new = self.hamiltonian.local(&self.grid, &[idx_1, idx_2]);
 
-        let delta_e = new_energy - old_energy;
+        let delta_e = self.hamiltonian.delta_energy(&old, &new);
 
+        if self.metropolis(beta, delta_e) {
+            self.hamiltonian.accept(&old, &new);
+        } else {
+            self.swap(idx_1, idx_2);
+        }
+    }
+
+    /// Performs a grand canonical Monte Carlo step,
+    /// where a single cyanometalate is either inserted or removed.
+    /// mu is the chemical potential of the cyanometalate and $\beta = \frac{1}{T}$
+    pub fn grand_canonical_step(&mut self, beta: f32, mu: f32) {
+        let idx = self.uniform_idx();
+        let old = self.hamiltonian.local(&self.grid, &[idx]);
+
+        self.grid[idx] = -self.grid[idx];
+
+        let new = self.hamiltonian.local(&self.grid, &[idx]);
+
+        // the site now holds 1 if a cyanometalate was inserted and -1 if it was removed
+        let delta_n = self.grid[idx];
+        let delta = self.hamiltonian.delta_energy(&old, &new) - mu * delta_n as f32;
+
+        if self.metropolis(beta, delta) {
+            self.hamiltonian.accept(&old, &new);
+            if delta_n == 1 {
+                self.metalates += 1;
+            } else {
+                self.metalates -= 1;
+            }
+        } else {
+            self.grid[idx] = -self.grid[idx];
+        }
+    }
+
+    /// Decides with the Metropolis criterion if a move is accepted
+    /// and updates the move counters
+    fn metropolis(&mut self, beta: f32, delta_e: f32) -> bool {
         if delta_e <= 0.0 {
-            self.nearest_neighbours += (new_n_neighbours - old_n_neighbours) as i64;
-            self.next_nearest_neighbours += (new_n_n_neighbours - old_n_n_neighbours) as i64;
             self.good_moves += 1;
+            true
         } else if self.rng.gen::<f32>() < (-beta * delta_e).exp() {
-            self.nearest_neighbours += (new_n_neighbours - old_n_neighbours) as i64;
-            self.next_nearest_neighbours += (new_n_n_neighbours - old_n_n_neighbours) as i64;
             self.bad_moves += 1;
+            true
         } else {
-            self.swap(idx_1, idx_2);
             self.rejected_moves += 1;
+            false
         }
     }
 
@@ -236,19 +268,31 @@ impl<const S: usize> Model<S> {
     }
 }
 
-impl<const S: usize> Model<S> {
+impl<H: Hamiltonian> Model<H> {
     /// Gets the hamiltonian
     pub fn get_hamiltonian(&self) -> f32 {
-        self.nearest_neighbours as f32 * self.j_1 + self.next_nearest_neighbours as f32 * self.j_2
+        self.hamiltonian.energy()
     }
 
-    /// Prints the nearest neighbour and next nearest neighbour sums
-    pub fn print_neighbours(&self) {
-        println!("nearest neighbours sum: {}", self.nearest_neighbours);
-        println!(
-            "next nearest neighbours sum: {}",
-            self.next_nearest_neighbours
-        );
+    /// Getter function for the energy model
+    pub fn hamiltonian(&self) -> &H {
+        &self.hamiltonian
+    }
+
+    /// Replaces the energy model while keeping the configuration,
+    /// e.g. to anneal along the couplings
+    pub fn set_hamiltonian(&mut self, hamiltonian: H) -> Result<(), ModelError> {
+        hamiltonian.check_dims(self.dims)?;
+        self.hamiltonian = hamiltonian;
+        self.calc_sums();
+        Ok(())
+    }
+
+    /// Prints the contributions of all terms of the hamiltonian
+    pub fn print_contributions(&self) {
+        for (name, energy) in self.hamiltonian.contributions() {
+            println!("{}: {}", name, energy);
+        }
     }
 
     /// Prints the move counters
@@ -258,22 +302,137 @@ impl<const S: usize> Model<S> {
         println!("rejected moves: {}", self.rejected_moves);
     }
 
+    /// Getter function for the numbers of good, bad and rejected moves
+    pub fn counters(&self) -> (u32, u32, u32) {
+        (self.good_moves, self.bad_moves, self.rejected_moves)
+    }
+
+    /// Getter function for the number of grid points along a, b and c
+    pub fn dims(&self) -> (usize, usize, usize) {
+        self.dims
+    }
+
+    /// The number of cyanometalate sites in the grid
+    pub fn sites(&self) -> usize {
+        let (a, b, c) = self.dims;
+        a * b * c / 2
+    }
+
+    /// Getter function for the number of cyanometalates
+    pub fn metalates(&self) -> usize {
+        self.metalates
+    }
+
     /// Getter function for the exact fill fraction
     pub fn fill_frac(&self) -> f64 {
-        let mut counter = 0;
-        for val in self.grid.as_flat_slice() {
-            if *val == 1 {
-                counter += 1;
-            }
-        }
-        counter as f64 / (S * S * S / 2) as f64
+        self.metalates as f64 / self.sites() as f64
     }
 
     /// Writes the grid to a cif file
     pub fn write_to_cif(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
-        let side = (S / 2) as f32 * DIST_MN_MN;
+        let (cell_a, cell_b, cell_c) = self.cell();
+        mmcif::write_mmcif(&self.grid, cell_a, cell_b, cell_c, Self::naming(), path)
+    }
+
+    /// Calculates |F(hkl)|^2 of the model with Mn on the metal sites and Co(CN)_6
+    /// on the cyanometalate sites, up to max_hkl in units of the reciprocal average unit cell.
+    /// The result is given on the reciprocal lattice of the model, so the step of the
+    /// average hkl is one over the number of unit cells
+    pub fn diffuse_scattering(&self, max_hkl: f32) -> DiffuseScattering {
+        let (cell_a, cell_b, cell_c) = self.cell();
+        diffuse_scattering(
+            &self.grid,
+            cell_a,
+            cell_b,
+            cell_c,
+            &Self::naming(),
+            self.max_hkl(max_hkl),
+        )
+        .expect("there are form factors for all ions")
+    }
+
+    /// Calculates |F(hkl)|^2 of the deviations of the model from its average structure
+    /// like diffuse_scattering, so there are no Bragg peaks
+    pub fn difference_scattering(&self, max_hkl: f32) -> DiffuseScattering {
+        let (cell_a, cell_b, cell_c) = self.cell();
+        difference_scattering(
+            &self.grid,
+            cell_a,
+            cell_b,
+            cell_c,
+            &Self::naming(),
+            self.max_hkl(max_hkl),
+        )
+        .expect("there are form factors for all ions")
+    }
+
+    /// The largest h, k and l on the reciprocal lattice of the model up to max_hkl
+    /// in units of the reciprocal average unit cell
+    fn max_hkl(&self, max_hkl: f32) -> (usize, usize, usize) {
+        let (a, b, c) = self.dims;
+        let max = |grid_points: usize| (max_hkl * (grid_points / 2) as f32).floor() as usize;
+        (max(a), max(b), max(c))
+    }
+
+    /// Writes the 3D-ΔPDF of intensities calculated by difference_scattering from models
+    /// of this size as a Yell .h5 file in direct space on the cubic average unit cell
+    pub fn write_delta_pdf(
+        &self,
+        scattering: &DiffuseScattering,
+        path: impl AsRef<Path>,
+    ) -> std::io::Result<()> {
+        let (a, b, c) = self.dims;
+        scattering
+            .delta_pdf()
+            .to_yell((a / 2, b / 2, c / 2), Self::unit_cell())
+            .write(path)
+    }
+
+    /// Writes |F(hkl)|^2 of the model up to max_hkl as a Yell .h5 file
+    /// on the cubic average unit cell
+    pub fn write_diffuse_scattering(
+        &self,
+        path: impl AsRef<Path>,
+        max_hkl: f32,
+    ) -> std::io::Result<()> {
+        self.write_scattering(&self.diffuse_scattering(max_hkl), path)
+    }
+
+    /// Writes intensities calculated from models of this size, for example averaged
+    /// over several snapshots, as a Yell .h5 file on the cubic average unit cell
+    pub fn write_scattering(
+        &self,
+        scattering: &DiffuseScattering,
+        path: impl AsRef<Path>,
+    ) -> std::io::Result<()> {
+        self.scattering_volume(scattering).write(path)
+    }
+
+    /// Intensities calculated from models of this size as a Yell volume on the cubic average unit cell
+    pub fn scattering_volume<'a>(&self, scattering: &'a DiffuseScattering) -> YellVolume<'a> {
+        let (a, b, c) = self.dims;
+        scattering.to_yell((a / 2, b / 2, c / 2), Self::unit_cell())
+    }
 
-        let naming = HashMap::from([
+    /// The lengths and angles of the cubic average unit cell
+    fn unit_cell() -> [f64; 6] {
+        let cell = DIST_MN_MN as f64;
+        [cell, cell, cell, 90.0, 90.0, 90.0]
+    }
+
+    /// The lengths of the whole model along a, b and c in Ångström
+    fn cell(&self) -> (f32, f32, f32) {
+        let (a, b, c) = self.dims;
+        (
+            (a / 2) as f32 * DIST_MN_MN,
+            (b / 2) as f32 * DIST_MN_MN,
+            (c / 2) as f32 * DIST_MN_MN,
+        )
+    }
+
+    /// The ions placed at the values of the grid
+    fn naming() -> HashMap<i8, Option<Ion>> {
+        HashMap::from([
             (0, Some(Ion::Singlet("Mn"))),
             (
                 1,
@@ -284,72 +443,379 @@ impl<const S: usize> Model<S> {
                 }),
             ),
             (-1, None),
-        ]);
-        mmcif::write_mmcif(&self.grid, side, side, side, naming, path)
+        ])
     }
 }
 
-impl<const S: usize> Model<S> {
+impl Model<PairHamiltonian> {
+    /// Prints the neighbour sums of all shells
+    pub fn print_neighbours(&self) {
+        for (n, sum) in self.hamiltonian.neighbour_sums().iter().enumerate() {
+            println!("shell {} neighbours sum: {}", n + 1, sum);
+        }
+    }
+
     /// This function saves the model to a .txt file.
-    /// note that the state of the rng is not preserved in this step
+    /// note that the state of the rng is not preserved in this step, a Checkpoint keeps it
     pub fn safe_to_txt(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
         let mut file = std::fs::File::create(path)?;
-        writeln!(file, "{} model size", S)?;
-        writeln!(file, "{} j_1", self.j_1)?;
-        writeln!(file, "{} j_2", self.j_2)?;
+        self.write_txt(&mut file)?;
+        file.flush()
+    }
+
+    /// Writes the model in the .txt format
+    fn write_txt(&self, file: &mut impl Write) -> std::io::Result<()> {
+        let (a, b, c) = self.dims;
+        writeln!(file, "{} {} {} model size", a, b, c)?;
+        for (n, j) in self.hamiltonian.couplings().iter().enumerate() {
+            writeln!(file, "{} j_{}", j, n + 1)?;
+        }
         writeln!(file, "{} good moves", self.good_moves)?;
         writeln!(file, "{} bad moves", self.bad_moves)?;
         writeln!(file, "{} rejected moves", self.rejected_moves)?;
-        writeln!(file, "{}", self.grid.as_string())?;
-        file.flush()?;
-        Ok(())
+        writeln!(file, "{}", self.grid.as_string())
     }
 
     /// This function reads a .txt file and recreates the model
-    /// note that the generic parameter S needs to be given correctly
-    /// and that the state of the rng is not preserved
-    pub fn from_txt(path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+    /// the size of the model is read from the file,
+    /// files with a single size are read as cubic models
+    /// note that the state of the rng is not preserved
+    pub fn from_txt(path: impl AsRef<Path>) -> Result<Self, ModelError> {
         let string = std::fs::read_to_string(path)?;
-        let mut split = string.split("\n");
-        assert_eq!(S, parse_next(&mut split)?, "incorrect generic argument s");
+        Self::parse_txt(&mut LineReader::new(&string))
+    }
+
+    /// Recreates the model from the lines of the .txt format
+    fn parse_txt(reader: &mut LineReader) -> Result<Self, ModelError> {
+        let dims = reader.parse_dims()?;
+        let couplings = reader.parse_couplings()?;
+        let hamiltonian = PairHamiltonian::new(couplings);
+        hamiltonian.check_dims(dims)?;
         let mut out = Self {
-            j_1: parse_next(&mut split)?,
-            j_2: parse_next(&mut split)?,
-            good_moves: parse_next(&mut split)?,
-            bad_moves: parse_next(&mut split)?,
-            rejected_moves: parse_next(&mut split)?,
-            grid: Array3d::<i8, S, S, S>::from_string(split.next().ok_or(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "not enough lines",
-            ))?)?,
+            dims,
+            hamiltonian,
+            metalates: 0,
+            good_moves: reader.parse_next("good moves")?,
+            bad_moves: reader.parse_next("bad moves")?,
+            rejected_moves: reader.parse_next("rejected moves")?,
+            grid: reader.parse_grid(dims)?,
             rng: SeedableRng::from_entropy(),
-            nearest_neighbours: 0,
-            next_nearest_neighbours: 0,
         };
+        out.metalates = out
+            .grid
+            .as_flat_slice()
+            .iter()
+            .filter(|val| **val == 1)
+            .count();
         out.calc_sums();
         Ok(out)
     }
 }
 
-/// Takes the next value of the iterator splits it by " " and parses the first item.
-fn parse_next<'a, T>(
-    iter: &mut impl Iterator<Item = &'a str>,
-) -> Result<T, Box<dyn std::error::Error>>
-where
-    T: std::str::FromStr,
-    <T as FromStr>::Err: 'static + std::error::Error,
-{
-    Ok(iter
-        .next()
-        .ok_or(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            "the file contained invalid data",
-        ))?
-        .split_whitespace()
-        .next()
-        .ok_or(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            "the file contained invalid data",
-        ))?
-        .parse::<T>()?)
+/// Reads a text file line by line and keeps track of the line number for errors
+struct LineReader<'a> {
+    lines: std::iter::Peekable<std::str::Split<'a, char>>,
+    /// The number of the last line that was taken
+    line: usize,
+}
+
+impl<'a> LineReader<'a> {
+    fn new(string: &'a str) -> Self {
+        Self {
+            lines: string.split('\n').peekable(),
+            line: 0,
+        }
+    }
+
+    /// Takes the next line
+    fn next_line(&mut self, field: &'static str) -> Result<&'a str, ModelError> {
+        self.line += 1;
+        self.lines.next().ok_or(ModelError::MissingField {
+            line: self.line,
+            field,
+        })
+    }
+
+    /// Takes the next line, splits it by " " and parses the first item.
+    fn parse_next<T: FromStr>(&mut self, field: &'static str) -> Result<T, ModelError> {
+        let line = self.next_line(field)?;
+        let value = line.split_whitespace().next().unwrap_or("");
+        value.parse().map_err(|_| ModelError::InvalidField {
+            line: self.line,
+            field,
+            value: value.to_string(),
+        })
+    }
+
+    /// Parses the line containing the size of the model.
+    /// The line either contains the three extents or a single one for cubic models.
+    fn parse_dims(&mut self) -> Result<(usize, usize, usize), ModelError> {
+        let field = "model size";
+        let line = self.next_line(field)?;
+        let values = line
+            .split_whitespace()
+            .map_while(|s| s.parse::<usize>().ok())
+            .collect::<Vec<_>>();
+        let dims = match values[..] {
+            [size] => (size, size, size),
+            [a, b, c] => (a, b, c),
+            _ => {
+                return Err(ModelError::InvalidField {
+                    line: self.line,
+                    field,
+                    value: line.to_string(),
+                })
+            }
+        };
+        let (a, b, c) = dims;
+        if a.is_multiple_of(2) && b.is_multiple_of(2) && c.is_multiple_of(2) {
+            Ok(dims)
+        } else {
+            Err(ModelError::OddDims { dims })
+        }
+    }
+
+    /// Parses the lines containing the interaction energies of the shells.
+    /// These are all consecutive lines labeled j_n.
+    fn parse_couplings(&mut self) -> Result<Vec<f32>, ModelError> {
+        let mut couplings = Vec::new();
+        while let Some(line) = self.lines.peek() {
+            match line.split_whitespace().nth(1) {
+                Some(label) if label.starts_with("j_") => {
+                    couplings.push(self.parse_next("coupling")?)
+                }
+                _ => break,
+            }
+        }
+        if couplings.len() > MAX_SHELLS {
+            return Err(ModelError::TooManyShells {
+                found: couplings.len(),
+                supported: MAX_SHELLS,
+            });
+        }
+        Ok(couplings)
+    }
+
+    /// Parses the line containing all values of the grid
+    fn parse_grid(&mut self, dims: (usize, usize, usize)) -> Result<Array3d<i8>, ModelError> {
+        let field = "grid";
+        let line = self.next_line(field)?;
+        let (a, b, c) = dims;
+        let mut grid = Array3d::new(a, b, c);
+        let mut values = line.split_whitespace();
+        let mut found = 0;
+        for (v, s) in grid.as_flat_slice_mut().iter_mut().zip(&mut values) {
+            *v = s.parse().map_err(|_| ModelError::InvalidField {
+                line: self.line,
+                field,
+                value: s.to_string(),
+            })?;
+            found += 1;
+        }
+        found += values.count();
+        if found != a * b * c {
+            return Err(ModelError::SizeMismatch {
+                field,
+                expected: a * b * c,
+                found,
+            });
+        }
+        validate_grid(&grid)?;
+        Ok(grid)
+    }
+}
+
+/// Checks that the metal sites contain 0 and the cyanometalate sites -1 or 1
+fn validate_grid(grid: &Array3d<i8>) -> Result<(), ModelError> {
+    let (a, b, c) = grid.dims();
+    for i in 0..a as isize {
+        for j in 0..b as isize {
+            for k in 0..c as isize {
+                let value = grid[(i, j, k)];
+                let is_valid = if (i + j + k) % 2 == 0 {
+                    value == 0
+                } else {
+                    value == 1 || value == -1
+                };
+                if !is_valid {
+                    return Err(ModelError::InvalidCell {
+                        index: (i, j, k),
+                        value,
+                    });
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn sizes_are_chosen_at_runtime() {
+        for size in [4, 6, 10] {
+            let mut model =
+                Model::new((size, size, size), vec![-1.0, 0.5], 0.5, Some("runtime")).unwrap();
+            assert_eq!(model.dims(), (size, size, size));
+            assert_eq!(model.grid.dims(), (size, size, size));
+            assert_eq!(model.sites(), size * size * size / 2);
+
+            // the bookkeeping of the steps agrees with recalculating the sums from the grid
+            for _ in 0..20 * model.sites() {
+                model.monte_carlo_step(1.0);
+            }
+            let mut recalculated = PairHamiltonian::new(vec![-1.0, 0.5]);
+            recalculated.calc_sums(&model.grid);
+            assert_eq!(
+                model.hamiltonian().neighbour_sums(),
+                recalculated.neighbour_sums()
+            );
+
+            // the size of a saved model is read from the file
+            let mut txt = Vec::new();
+            model.write_txt(&mut txt).unwrap();
+            let txt = String::from_utf8(txt).unwrap();
+            let loaded = Model::parse_txt(&mut LineReader::new(&txt)).unwrap();
+            assert_eq!(loaded.dims(), model.dims());
+            assert_eq!(loaded.grid.as_flat_slice(), model.grid.as_flat_slice());
+            assert_eq!(loaded.get_hamiltonian(), model.get_hamiltonian());
+        }
+    }
+
+    #[test]
+    fn supercells_with_independent_extents() {
+        let dims = (4, 6, 8);
+        let mut model = Model::new(dims, vec![-1.0, 0.5], 0.5, Some("slab")).unwrap();
+        assert_eq!(model.sites(), 96);
+        assert_eq!(model.metalates(), 48);
+
+        // the indexes of the sites can lie outside of the grid and wrap around
+        let wrap = |(i, j, k): Index| (i.rem_euclid(4), j.rem_euclid(6), k.rem_euclid(8));
+        let sites: HashSet<Index> = cyanometalate_sites(dims).map(wrap).collect();
+        assert_eq!(sites.len(), 96);
+        assert!(sites.iter().all(|(i, j, k)| (i + j + k) % 2 == 1));
+        let drawn: HashSet<Index> = (0..10_000).map(|_| wrap(model.uniform_idx())).collect();
+        assert_eq!(drawn, sites);
+
+        let path = std::env::temp_dir().join(format!("pba_slab_{}.mmcif", std::process::id()));
+        model.write_to_cif(&path).unwrap();
+        let cif = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        for (axis, cells) in [("a", 2.0), ("b", 3.0), ("c", 4.0)] {
+            let line = format!("_cell.length_{} {}", axis, cells * DIST_MN_MN);
+            assert!(cif.contains(&line), "{}", line);
+        }
+    }
+
+    #[test]
+    fn grand_canonical_fill_fraction() {
+        // without interactions every site is occupied independently with 1 / (1 + exp(-beta mu))
+        for mu in [-1.0, 0.0, 0.5] {
+            let mut model = Model::new((6, 6, 6), vec![0.0, 0.0], 0.5, Some("grand")).unwrap();
+            let steps = model.sites();
+            for _ in 0..50 * steps {
+                model.grand_canonical_step(1.0, mu);
+            }
+            let mut fill = StreamingStats::new();
+            for _ in 0..500 {
+                for _ in 0..steps {
+                    model.grand_canonical_step(1.0, mu);
+                }
+                fill.add_value(model.fill_frac());
+            }
+            let expected = 1.0 / (1.0 + (-mu as f64).exp());
+            assert!(
+                (fill.avg() - expected).abs() < 0.01,
+                "{} != {}",
+                fill.avg(),
+                expected
+            );
+        }
+
+        // the composition and the energy are kept track of with interactions
+        let mut model = Model::new((6, 6, 6), vec![-1.0, 0.5], 0.5, Some("grand")).unwrap();
+        for _ in 0..100 * model.sites() {
+            model.grand_canonical_step(1.0, 0.3);
+        }
+        let metalates = model
+            .grid
+            .as_flat_slice()
+            .iter()
+            .filter(|v| **v == 1)
+            .count();
+        assert_eq!(model.metalates(), metalates);
+        let mut recalculated = PairHamiltonian::new(vec![-1.0, 0.5]);
+        recalculated.calc_sums(&model.grid);
+        assert_eq!(
+            model.hamiltonian().neighbour_sums(),
+            recalculated.neighbour_sums()
+        );
+    }
+
+    #[test]
+    fn errors_name_the_failing_field() {
+        let model = Model::new((4, 4, 4), vec![-1.0, 0.5], 0.5, Some("errors")).unwrap();
+        let mut txt = Vec::new();
+        model.write_txt(&mut txt).unwrap();
+        let txt = String::from_utf8(txt).unwrap();
+        let lines: Vec<&str> = txt.lines().collect();
+        let parse = |lines: &[&str]| Model::parse_txt(&mut LineReader::new(&lines.join("\n")));
+        assert!(parse(&lines).is_ok());
+
+        let mut odd = lines.clone();
+        odd[0] = "4 3 4 model size";
+        assert!(matches!(
+            parse(&odd),
+            Err(ModelError::OddDims { dims: (4, 3, 4) })
+        ));
+
+        // the size, the two couplings and then the move counters
+        let mut invalid = lines.clone();
+        invalid[4] = "many bad moves";
+        let err = parse(&invalid).unwrap_err();
+        assert!(matches!(
+            &err,
+            ModelError::InvalidField { line: 5, field: "bad moves", value } if value == "many"
+        ));
+        assert_eq!(
+            err.to_string(),
+            "line 5: could not parse bad moves from 'many'"
+        );
+
+        assert!(matches!(
+            parse(&lines[..3]),
+            Err(ModelError::MissingField {
+                line: 4,
+                field: "good moves"
+            })
+        ));
+
+        let grid: Vec<&str> = lines[6].split(' ').collect();
+        let short = grid[1..].join(" ");
+        let mut mismatch = lines.clone();
+        mismatch[6] = &short;
+        assert!(matches!(
+            parse(&mismatch),
+            Err(ModelError::SizeMismatch {
+                field: "grid",
+                expected: 64,
+                found: 63
+            })
+        ));
+
+        // the first value of the grid is the metal site (0, 0, 0)
+        let metal = format!("1 {}", grid[1..].join(" "));
+        let mut cell = lines.clone();
+        cell[6] = &metal;
+        assert!(matches!(
+            parse(&cell),
+            Err(ModelError::InvalidCell {
+                index: (0, 0, 0),
+                value: 1
+            })
+        ));
+    }
 }
@@ -1,5 +1,5 @@
 use std::{
-    fs::File,
+    fs::{File, OpenOptions},
     io::{BufWriter, Result, Write},
     sync::mpsc::{SendError, Sender},
     thread::JoinHandle,
@@ -15,19 +15,39 @@ impl CsvLogger {
     pub fn new(
         path: String,
         header: String,
-        categories: Vec<&'static str>,
+        categories: Vec<String>,
     ) -> (Self, JoinHandle<Result<()>>) {
-        let (tx, rx) = std::sync::mpsc::channel::<String>();
         let columns = categories.len();
-        let handle = std::thread::spawn(move || -> Result<()> {
-            let file = File::create(path)?;
-            let mut writer = BufWriter::new(file);
+        Self::spawn(columns, move || {
+            let mut writer = BufWriter::new(File::create(path)?);
             writeln!(writer, "{}", header)?;
             writeln!(writer, "{}", categories.join(","))?;
+            Ok(writer)
+        })
+    }
+
+    /// Appends rows to an existing csv file that was created by new with the same number of columns
+    pub fn append(path: String, columns: usize) -> (Self, JoinHandle<Result<()>>) {
+        Self::spawn(columns, move || {
+            Ok(BufWriter::new(OpenOptions::new().append(true).open(path)?))
+        })
+    }
+
+    /// Starts the thread writing the rows.
+    /// Every row is flushed, so only rows that are in flight are lost if the program is killed
+    fn spawn(
+        columns: usize,
+        open: impl FnOnce() -> Result<BufWriter<File>> + Send + 'static,
+    ) -> (Self, JoinHandle<Result<()>>) {
+        let (tx, rx) = std::sync::mpsc::channel::<String>();
+        let handle = std::thread::spawn(move || -> Result<()> {
+            let mut writer = open()?;
+            writer.flush()?;
             while let Ok(line) = rx.recv() {
                 writeln!(writer, "{}", line)?;
+                writer.flush()?;
             }
-            writer.flush()
+            Ok(())
         });
         (
             Self {
@@ -1,103 +1,540 @@
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 
-use chrono::Utc;
-use rayon::prelude::*;
+use clap::{Args, Parser, Subcommand};
 
-use pba::{CsvLogger, Model, StreamingStats};
-const J_2: f32 = 1.0;
+use pba::{
+    ferrenberg_swendsen, Anneal, DiffuseScattering, Grid, Manifest, Model, NeighbourHistogram,
+    Plane, Rendering, Section, SizeGrid, SweepConfig, SweepRunner,
+};
 
-const SIZE: usize = 32;
-const FILL_FRAC: f32 = 2.0 / 3.0;
+#[derive(Parser)]
+#[command(about = "Monte Carlo simulation of vacancies in Prussian blue analogues")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
 
-const EQ_EPOCHS: usize = 500;
-const EPOCH: usize = 500;
+#[derive(Subcommand)]
+enum Command {
+    /// Runs a sweep over all combinations of the given parameters
+    Sweep(Parameters),
+    /// Runs a single point, all parameters need to have a single value
+    Point(Parameters),
+    /// Computes the missing points of an interrupted run
+    Resume {
+        /// The name of the run
+        run: String,
+        /// The directory containing the csv, mmcif and models directories
+        #[arg(long, default_value = "out")]
+        output: String,
+    },
+    /// Combines the histograms of a run and reweights them to other values of the couplings and temperature,
+    /// the grids default to the ones of the run
+    Reweight {
+        /// The name of the run
+        run: String,
+        /// The directory containing the csv, models and histograms directories
+        #[arg(long, default_value = "out")]
+        output: String,
+        #[arg(long, allow_hyphen_values = true)]
+        j_2: Option<Grid>,
+        #[arg(long, allow_hyphen_values = true)]
+        j_prime: Option<Grid>,
+        #[arg(long, allow_hyphen_values = true)]
+        temp: Option<Grid>,
+        /// The grids of the further shells, which need to be included in the run
+        #[arg(long, allow_hyphen_values = true)]
+        j_3: Option<Grid>,
+        #[arg(long, allow_hyphen_values = true)]
+        j_4: Option<Grid>,
+        #[arg(long, allow_hyphen_values = true)]
+        j_5: Option<Grid>,
+    },
+    /// Converts a model between the .txt and the binary .bin format
+    Convert { input: PathBuf, output: PathBuf },
+    /// Writes a model as a mmcif file
+    Cif { model: PathBuf, output: PathBuf },
+    /// Writes the diffuse scattering of a model as a Yell .h5 file
+    Scattering {
+        model: PathBuf,
+        output: PathBuf,
+        /// The largest h, k and l in units of the reciprocal average unit cell
+        #[arg(long, default_value_t = 5.0)]
+        max_hkl: f32,
+    },
+    /// Writes a section of the diffuse scattering of a model as a png
+    Section {
+        model: PathBuf,
+        output: PathBuf,
+        /// The plane like `hk0`, `h0.5l`, `hhl` or `1,1,0:0,0,1` for the plane spanned by 110 and 001
+        #[arg(long, default_value = "hk0")]
+        plane: Plane,
+        /// The largest h, k and l in units of the reciprocal average unit cell
+        #[arg(long, default_value_t = 5.0)]
+        max_hkl: f32,
+        /// Average the section over the cubic symmetry
+        #[arg(long)]
+        symmetrize: bool,
+        /// The percentiles that are clamped at the low and at the high end
+        #[arg(long, num_args = 2, value_names = ["LOW", "HIGH"], default_values_t = [0.0, 0.0])]
+        clamp_percentiles: Vec<f32>,
+        /// Show the intensities instead of their logarithm
+        #[arg(long)]
+        linear: bool,
+    },
+    /// Writes the 3D-ΔPDF averaged over models of the same size as a Yell .h5 file
+    DeltaPdf {
+        output: PathBuf,
+        #[arg(required = true)]
+        models: Vec<PathBuf>,
+        /// The largest h, k and l of the intensities in units of the reciprocal average unit cell
+        #[arg(long, default_value_t = 5.0)]
+        max_hkl: f32,
+    },
+}
 
-const J_STEPS: u32 = 16;
-const J_START: f32 = 6.0;
-const J_END: f32 = 0.0;
+/// The parameters of a simulation.
+/// Values given as flags override the ones from the config file.
+/// Grids are given as a single value `1.5`, a list `0,0.5,2`,
+/// evenly spaced values `start:end:steps` or geometrically spaced values `start:end:steps:log`
+#[derive(Args)]
+struct Parameters {
+    /// A toml file containing the parameters
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// The number of grid points along a, b and c, can be repeated to scan several sizes
+    #[arg(long, num_args = 3, value_names = ["A", "B", "C"])]
+    size: Vec<usize>,
+    /// The grid of the initial fill fractions
+    #[arg(long, allow_hyphen_values = true)]
+    fill_frac: Option<Grid>,
+    /// The grid of J_2
+    #[arg(long, allow_hyphen_values = true)]
+    j_2: Option<Grid>,
+    /// The grid of J' = J_1 / J_2
+    #[arg(long, allow_hyphen_values = true)]
+    j_prime: Option<Grid>,
+    /// The grid of the absolute temperatures
+    #[arg(long, allow_hyphen_values = true)]
+    temp: Option<Grid>,
+    /// The grid of J_3, which includes the third shell of neighbours
+    #[arg(long, allow_hyphen_values = true)]
+    j_3: Option<Grid>,
+    /// The grid of J_4, which includes the fourth shell and requires J_3
+    #[arg(long, allow_hyphen_values = true)]
+    j_4: Option<Grid>,
+    /// The grid of J_5, which includes the fifth shell and requires J_4
+    #[arg(long, allow_hyphen_values = true)]
+    j_5: Option<Grid>,
+    /// The parameter changed along a chain: temp, j_prime, j_2 or independent
+    #[arg(long)]
+    anneal: Option<Anneal>,
+    /// The number of equilibration sweeps, or the maximum with automatic equilibration
+    #[arg(long)]
+    eq_epochs: Option<usize>,
+    /// Always equilibrate for eq_epochs sweeps instead of stopping when the energy is stationary
+    #[arg(long)]
+    fixed_equilibration: bool,
+    #[arg(long)]
+    min_eq_epochs: Option<usize>,
+    #[arg(long)]
+    epoch: Option<usize>,
+    #[arg(long, allow_negative_numbers = true)]
+    chemical_potential: Option<f32>,
+    #[arg(long)]
+    tempering: bool,
+    #[arg(long)]
+    exchange_interval: Option<usize>,
+    /// Write the energy, neighbour sums and move counters of every sweep
+    #[arg(long)]
+    trajectories: bool,
+    /// Write the histogram of the neighbour sums of every point for reweighting
+    #[arg(long)]
+    histograms: bool,
+    /// Write the Warren-Cowley parameters of all vectors up to this length in unit cells
+    #[arg(long)]
+    sro_radius: Option<f32>,
+    /// The number of sampled sweeps between two snapshots of the short range order
+    #[arg(long)]
+    sro_interval: Option<usize>,
+    /// Average |F(hkl)|^2 up to this hkl over snapshots of every point and write it in the Yell format
+    #[arg(long)]
+    scattering_max_hkl: Option<f32>,
+    /// The number of sampled sweeps between two snapshots of the diffuse scattering
+    #[arg(long)]
+    scattering_interval: Option<usize>,
+    /// Write this section of the scattering of every point and a map of all sections,
+    /// like `hk0`, `hhl` or `1,1,0:0,0,1` for the plane spanned by 110 and 001
+    #[arg(long)]
+    section: Option<Plane>,
+    /// Average the sections over the cubic symmetry
+    #[arg(long)]
+    symmetrize: bool,
+    /// The percentiles of the sections that are clamped at the low and at the high end
+    #[arg(long, num_args = 2, value_names = ["LOW", "HIGH"])]
+    clamp_percentiles: Vec<f32>,
+    #[arg(long)]
+    seed: Option<String>,
+    /// The directory containing the csv, mmcif, models and trajectories directories
+    #[arg(long)]
+    output: Option<String>,
+}
 
-const TEMP_STEPS: u32 = 40;
-const LN_T_PRIME_0: f32 = 4.0;
-const LN_T_PRIME_END: f32 = -2.0;
+impl Parameters {
+    /// Reads the config file if there is one and applies the flags
+    fn into_config(self) -> Result<SweepConfig, Box<dyn std::error::Error>> {
+        let mut config = match &self.config {
+            Some(path) => SweepConfig::from_toml(path)?,
+            None => SweepConfig::default(),
+        };
+        match self.size.as_slice() {
+            [] => (),
+            [a, b, c] => config.size = SizeGrid::Single((*a, *b, *c)),
+            sizes => {
+                config.size = SizeGrid::List(
+                    sizes
+                        .chunks_exact(3)
+                        .map(|size| (size[0], size[1], size[2]))
+                        .collect(),
+                )
+            }
+        }
+        if let Some(fill_frac) = self.fill_frac {
+            config.fill_frac = fill_frac;
+        }
+        if let Some(j_2) = self.j_2 {
+            config.j_2 = j_2;
+        }
+        if let Some(j_prime) = self.j_prime {
+            config.j_prime = j_prime;
+        }
+        if let Some(temp) = self.temp {
+            config.temp = temp;
+        }
+        if self.j_3.is_some() {
+            config.j_3 = self.j_3;
+        }
+        if self.j_4.is_some() {
+            config.j_4 = self.j_4;
+        }
+        if self.j_5.is_some() {
+            config.j_5 = self.j_5;
+        }
+        if let Some(anneal) = self.anneal {
+            config.anneal = anneal;
+        }
+        if let Some(eq_epochs) = self.eq_epochs {
+            config.eq_epochs = eq_epochs;
+        }
+        if self.fixed_equilibration {
+            config.auto_equilibration = false;
+        }
+        if let Some(min_eq_epochs) = self.min_eq_epochs {
+            config.min_eq_epochs = min_eq_epochs;
+        }
+        if let Some(epoch) = self.epoch {
+            config.epoch = epoch;
+        }
+        if self.chemical_potential.is_some() {
+            config.chemical_potential = self.chemical_potential;
+        }
+        if self.tempering {
+            config.tempering = true;
+        }
+        if let Some(exchange_interval) = self.exchange_interval {
+            config.exchange_interval = exchange_interval;
+        }
+        if self.trajectories {
+            config.trajectories = true;
+        }
+        if self.histograms {
+            config.histograms = true;
+        }
+        if self.sro_radius.is_some() {
+            config.sro_radius = self.sro_radius;
+        }
+        if let Some(sro_interval) = self.sro_interval {
+            config.sro_interval = sro_interval;
+        }
+        if self.scattering_max_hkl.is_some() {
+            config.scattering_max_hkl = self.scattering_max_hkl;
+        }
+        if let Some(scattering_interval) = self.scattering_interval {
+            config.scattering_interval = scattering_interval;
+        }
+        if self.section.is_some() {
+            config.section = self.section;
+        }
+        if self.symmetrize {
+            config.symmetrize = true;
+        }
+        if let [low, high] = self.clamp_percentiles[..] {
+            config.clamp_percentiles = [low, high];
+        }
+        if self.seed.is_some() {
+            config.seed = self.seed;
+        }
+        if let Some(output) = self.output {
+            config.output = output;
+        }
+        Ok(config)
+    }
+}
 
 fn main() {
-    let name = format!("{}", Utc::now().format("%Y-%m-%d_%H-%M"));
-    std::fs::create_dir(&format!("out/mmcif/{}", name)).unwrap();
-    std::fs::create_dir(&format!("out/models/{}", name)).unwrap();
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Command::Sweep(parameters) => parameters
+            .into_config()
+            .and_then(SweepRunner::new)
+            .and_then(run_sweep),
+        Command::Point(parameters) => parameters.into_config().and_then(|config| {
+            if config.chains().concat().len() != 1 {
+                return Err("a point needs a single value for every parameter".into());
+            }
+            run_sweep(SweepRunner::new(config)?)
+        }),
+        Command::Resume { run, output } => SweepRunner::resume(output, &run).and_then(run_sweep),
+        Command::Reweight {
+            run,
+            output,
+            j_2,
+            j_prime,
+            temp,
+            j_3,
+            j_4,
+            j_5,
+        } => reweight(
+            Path::new(&output),
+            &run,
+            [j_2, j_prime, temp, j_3, j_4, j_5],
+        ),
+        Command::Convert { input, output } => convert(&input, &output),
+        Command::Cif { model, output } => {
+            load_model(&model).and_then(|model| Ok(model.write_to_cif(output)?))
+        }
+        Command::Scattering {
+            model,
+            output,
+            max_hkl,
+        } => load_model(&model)
+            .and_then(|model| Ok(model.write_diffuse_scattering(output, max_hkl)?)),
+        Command::Section {
+            model,
+            output,
+            plane,
+            max_hkl,
+            symmetrize,
+            clamp_percentiles,
+            linear,
+        } => load_model(&model).and_then(|model| {
+            let scattering = model.diffuse_scattering(max_hkl);
+            let section = Section::new(&model.scattering_volume(&scattering), &plane, symmetrize)?;
+            let rendering = Rendering {
+                log: !linear,
+                clamp: (clamp_percentiles[0] as f64, clamp_percentiles[1] as f64),
+            };
+            Ok(section.write_png(output, &rendering)?)
+        }),
+        Command::DeltaPdf {
+            output,
+            models,
+            max_hkl,
+        } => delta_pdf(&output, &models, max_hkl),
+    };
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
+
+/// Averages the difference scattering of the models and writes its 3D-ΔPDF
+fn delta_pdf(
+    output: &Path,
+    models: &[PathBuf],
+    max_hkl: f32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut first: Option<Model> = None;
+    let mut scattering: Option<DiffuseScattering> = None;
+    for path in models {
+        let model = load_model(path)?;
+        if let Some(first) = &first {
+            if first.dims() != model.dims() {
+                return Err(format!(
+                    "{} has the size {:?} instead of {:?}",
+                    path.display(),
+                    model.dims(),
+                    first.dims()
+                )
+                .into());
+            }
+        }
+        let snapshot = model.difference_scattering(max_hkl);
+        match &mut scattering {
+            Some(scattering) => scattering.merge(&snapshot),
+            None => scattering = Some(snapshot),
+        }
+        first.get_or_insert(model);
+    }
+    let (Some(model), Some(scattering)) = (first, scattering) else {
+        return Err("there are no models".into());
+    };
+    Ok(model.write_delta_pdf(&scattering, output)?)
+}
 
-    let temps: Vec<f32> = (0..TEMP_STEPS)
-        .map(|i| {
-            ((LN_T_PRIME_END - LN_T_PRIME_0) / (TEMP_STEPS - 1) as f32 * i as f32 + LN_T_PRIME_0)
-                .exp()
-                * J_2
-        })
-        .collect();
+/// Estimates the density of states from the histograms of a run
+/// and writes the thermodynamics on the grid to csv/{run}_reweighted.csv.
+/// Histograms of different sizes or compositions can not be combined,
+/// if the run has several of them every one is written to csv/{run}_reweighted_{metalates}_of_{sites}.csv
+/// The grids override J_2, J', the temperature, J_3, J_4 and J_5 of the run
+fn reweight(
+    output: &Path,
+    run: &str,
+    grids: [Option<Grid>; 6],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let manifest = Manifest::from_toml(output.join("models").join(run).join("manifest.toml"))?;
+    let histograms = manifest
+        .files
+        .iter()
+        .filter(|file| file.starts_with("histograms/"))
+        .map(|file| NeighbourHistogram::from_csv(output.join(file)))
+        .collect::<Result<Vec<_>, _>>()?;
+    if histograms.is_empty() {
+        return Err(format!("{} has no histograms, run it with --histograms", run).into());
+    }
+    // the reweighted points only differ in the couplings and the temperature
+    let mut parameters = manifest.parameters;
+    parameters.size = SizeGrid::Single(parameters.size.values()[0]);
+    parameters.fill_frac = Grid::Single(parameters.fill_frac.values()[0]);
+    let [j_2, j_prime, temp, j_3, j_4, j_5] = grids;
+    if let Some(j_2) = j_2 {
+        parameters.j_2 = j_2;
+    }
+    if let Some(j_prime) = j_prime {
+        parameters.j_prime = j_prime;
+    }
+    if let Some(temp) = temp {
+        parameters.temp = temp;
+    }
+    for (shell, grid) in [
+        &mut parameters.j_3,
+        &mut parameters.j_4,
+        &mut parameters.j_5,
+    ]
+    .into_iter()
+    .zip([j_3, j_4, j_5])
+    {
+        if grid.is_some() {
+            *shell = grid;
+        }
+    }
+    parameters.validate()?;
+    let points = parameters.points();
 
-    let j_primes: Vec<f32> = (0..J_STEPS)
-        .map(|i| i as f32 / (J_STEPS - 1) as f32 * (J_END - J_START) + J_START)
-        .collect();
+    let mut groups: Vec<((usize, usize, usize), Vec<NeighbourHistogram>)> = Vec::new();
+    for histogram in histograms {
+        let key = (
+            histogram.sites(),
+            histogram.metalates(),
+            histogram.couplings().len(),
+        );
+        match groups.iter_mut().find(|(group, _)| *group == key) {
+            Some((_, histograms)) => histograms.push(histogram),
+            None => groups.push((key, vec![histogram])),
+        }
+    }
+    groups.sort_by_key(|(key, _)| *key);
 
-    let (logger, handle) = CsvLogger::new(
-        format!("out/csv/{}.csv", name),
-        format!(
-            "energy and variance are give per cyanometalate site\n{} supercells in every direction\n{} fill fraction",
-            SIZE/2,
-            (FILL_FRAC*(SIZE*SIZE*SIZE/2) as f32).floor() as usize as f32 / (SIZE*SIZE*SIZE/2) as f32
-        ),
-        vec!["j_prime", "temp", "energy", "variance"],
-    );
+    let couplings = points[0].couplings().len();
+    for ((sites, metalates, shells), histograms) in &groups {
+        if couplings != *shells {
+            return Err(format!(
+                "the histograms of {} have {} shells but the reweighted points have {} couplings",
+                run, shells, couplings
+            )
+            .into());
+        }
+        let density = ferrenberg_swendsen(histograms, 1e-7, 100_000).map_err(|err| {
+            format!(
+                "could not reweight the histograms of {} of {} sites: {}",
+                metalates, sites, err
+            )
+        })?;
+        let sites = *sites as f64;
+        let mut csv = format!(
+            "reweighted from {} histograms of {} sites
+energy, heat capacity, free energy and entropy are given per cyanometalate site
+{} fill fraction
+j_prime,temp,j_2,j_3,j_4,j_5,energy,heat_capacity,free_energy,entropy
+",
+            histograms.len(),
+            sites,
+            *metalates as f64 / sites
+        );
+        for point in &points {
+            let thermo = density.thermodynamics(&point.couplings(), point.temp as f64);
+            csv += &format!(
+                "{},{},{},{},{},{},{},{},{},{}\n",
+                point.j_prime,
+                point.temp,
+                point.j_2,
+                point.j_3.unwrap_or(f32::NAN),
+                point.j_4.unwrap_or(f32::NAN),
+                point.j_5.unwrap_or(f32::NAN),
+                thermo.energy / sites,
+                thermo.heat_capacity / sites,
+                thermo.free_energy / sites,
+                thermo.entropy / sites
+            );
+        }
+        let name = match groups.len() {
+            1 => format!("{}_reweighted.csv", run),
+            _ => format!("{}_reweighted_{}_of_{}.csv", run, metalates, sites),
+        };
+        let path = output.join("csv").join(name);
+        std::fs::write(&path, csv)?;
+        println!("wrote {}", path.display());
+    }
+    Ok(())
+}
 
-    let start = Instant::now();
-    let _: Vec<_> = j_primes
-        .par_iter()
-        .map_with(logger, |logger, j_prime| {
-            let mut model = Model::<SIZE>::new(j_prime * J_2, J_2, FILL_FRAC, None);
-            for temp in &temps {
-                for _ in 0..EQ_EPOCHS {
-                    for _ in 0..SIZE * SIZE * SIZE {
-                        model.monte_carlo_step(1.0 / temp)
-                    }
-                }
+/// Loads a model from a .txt or .bin file
+fn load_model(path: &Path) -> Result<Model, Box<dyn std::error::Error>> {
+    let model = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("txt") => Model::from_txt(path)?,
+        Some("bin") => Model::from_binary(path)?,
+        _ => return Err("models need to end with .txt or .bin".into()),
+    };
+    Ok(model)
+}
 
-                let mut stats = StreamingStats::new();
-                for _ in 0..EPOCH {
-                    for _ in 0..SIZE * SIZE * SIZE {
-                        model.monte_carlo_step(1.0 / temp);
-                        stats.add_value(model.get_hamiltonian())
-                    }
-                }
-                logger
-                    .send_row(vec![
-                        *j_prime,
-                        *temp,
-                        stats.avg() / (SIZE * SIZE * SIZE / 2) as f32,
-                        stats.variance() / (SIZE * SIZE * SIZE / 2) as f32,
-                    ])
-                    .expect("error while sending row to csv logger");
-                if let Result::Err(err) = model.write_to_cif(&format!(
-                    "out/mmcif/{}/j_{}_t_{}.mmcif",
-                    name, j_prime, temp
-                )) {
-                    eprintln!(
-                        "{}\ncould not create mmcif for j: {}, t: {}",
-                        err, j_prime, temp
-                    )
-                }
+/// Converts a model into the format given by the extension of output
+fn convert(input: &Path, output: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let model = load_model(input)?;
+    match output.extension().and_then(|ext| ext.to_str()) {
+        Some("txt") => model.safe_to_txt(output)?,
+        Some("bin") => model.save_to_binary(output)?,
+        _ => return Err("models need to end with .txt or .bin".into()),
+    }
+    Ok(())
+}
 
-                if let Result::Err(err) =
-                    model.safe_to_txt(&format!("out/models/{}/j_{}_t_{}.txt", name, j_prime, temp))
-                {
-                    eprintln!(
-                        "{}\ncould not create txt for j: {}, t: {}",
-                        err, j_prime, temp
-                    )
-                }
-            }
-        })
-        .collect();
-    handle
-        .join()
-        .unwrap()
-        .expect("error while joining handle of csv logger");
-    println!("finished in {}s", start.elapsed().as_secs_f32())
+/// Runs the sweep and reports the points that failed
+fn run_sweep(runner: SweepRunner) -> Result<(), Box<dyn std::error::Error>> {
+    let start = Instant::now();
+    let failures = runner.run()?;
+    println!(
+        "finished {} in {}s",
+        runner.name(),
+        start.elapsed().as_secs_f32()
+    );
+    if !failures.is_empty() {
+        return Err(format!(
+            "{} points failed, resume the run to retry them",
+            failures.len()
+        )
+        .into());
+    }
+    Ok(())
 }
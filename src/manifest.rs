@@ -0,0 +1,178 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::path::Path;
+
+use crate::{Point, SweepConfig};
+
+/// Everything needed to reproduce a run and to find its output.
+/// It is written as `manifest.toml` into the models directory of the run
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Manifest {
+    /// The name of the run, all outputs are placed in directories with this name
+    pub run: String,
+    /// The version of this crate that produced the run
+    pub version: String,
+    /// The start of the run in RFC 3339
+    pub start_time: String,
+    /// The end of the run in RFC 3339, missing if the run did not finish
+    pub end_time: Option<String>,
+    /// The number of sweeps for equilibration and sampling at every point,
+    /// with automatic equilibration eq_epochs is the maximum and
+    /// the actual number of sweeps is given in the eq_sweeps column of the csv file
+    pub eq_epochs: usize,
+    pub epoch: usize,
+    /// The seed of every chain, replicas of parallel tempering append `_t_{index}`
+    pub chains: Vec<Chain>,
+    /// The produced files relative to the output directory
+    pub files: Vec<String>,
+    /// The points whose results are written to the csv file and whose models are saved
+    #[serde(default)]
+    pub completed: Vec<Point>,
+    /// The points that failed in the last attempt, they are retried when the run is resumed
+    #[serde(default)]
+    pub failed: Vec<Failure>,
+    /// The parameters of the run
+    pub parameters: SweepConfig,
+}
+
+/// A single Markov chain of a run
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Chain {
+    /// The name of the chain as given by `SweepConfig::chain_name`
+    pub name: String,
+    pub seed: Option<String>,
+}
+
+/// A point or a whole chain that could not be completed
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Failure {
+    /// The name of the point or of the chain if the whole chain failed
+    pub name: String,
+    pub error: String,
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} failed: {}", self.name, self.error)
+    }
+}
+
+impl Manifest {
+    /// Creates the manifest of a run starting now
+    pub fn new(run: &str, parameters: &SweepConfig) -> Self {
+        Self {
+            run: run.to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            start_time: chrono::Utc::now().to_rfc3339(),
+            end_time: None,
+            eq_epochs: parameters.eq_epochs,
+            epoch: parameters.epoch,
+            chains: parameters
+                .chains()
+                .iter()
+                .map(|chain| Chain {
+                    name: parameters.chain_name(&chain[0]),
+                    seed: parameters.chain_seed(&chain[0]),
+                })
+                .collect(),
+            files: Vec::new(),
+            completed: Vec::new(),
+            failed: Vec::new(),
+            parameters: parameters.clone(),
+        }
+    }
+
+    /// Marks the run as finished now
+    pub fn finish(&mut self) {
+        self.end_time = Some(chrono::Utc::now().to_rfc3339());
+    }
+
+    /// Reads a manifest from a toml file
+    pub fn from_toml(path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    /// Writes the manifest to a toml file.
+    /// The file is replaced atomically, so a crash leaves either the old or the new manifest
+    pub fn write_toml(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("toml.tmp");
+        std::fs::write(&tmp, toml::to_string(self)?)?;
+        std::fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// Checks if the point is completed
+    pub fn is_completed(&self, point: &Point) -> bool {
+        self.completed.contains(point)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Grid, SizeGrid, SweepRunner};
+
+    #[test]
+    fn records_the_provenance_of_a_run() {
+        let out = std::env::temp_dir().join(format!("pba_manifest_{}", std::process::id()));
+        let config = SweepConfig {
+            size: SizeGrid::Single((4, 4, 4)),
+            fill_frac: Grid::Single(0.5),
+            j_prime: Grid::List(vec![0.0, 1.0]),
+            temp: Grid::List(vec![2.0, 1.0]),
+            eq_epochs: 10,
+            epoch: 10,
+            output: out.to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+        let runner = SweepRunner::new(config.clone()).unwrap();
+        let run = runner.name().to_string();
+        assert!(runner.run().unwrap().is_empty());
+
+        let manifest =
+            Manifest::from_toml(out.join("models").join(&run).join("manifest.toml")).unwrap();
+        // every file of the run is listed
+        for file in &manifest.files {
+            assert!(out.join(file).exists(), "{}", file);
+        }
+        std::fs::remove_dir_all(&out).unwrap();
+        assert_eq!(manifest.run, run);
+        assert_eq!(manifest.version, env!("CARGO_PKG_VERSION"));
+        assert!(manifest.end_time.is_some());
+        assert_eq!((manifest.eq_epochs, manifest.epoch), (10, 10));
+        assert!(manifest.failed.is_empty());
+
+        // the runner draws a seed, so the run can be reproduced from the manifest
+        let seed = manifest.parameters.seed.clone().unwrap();
+        assert_eq!(
+            manifest.parameters,
+            SweepConfig {
+                seed: Some(seed.clone()),
+                ..config
+            }
+        );
+        let chains: Vec<_> = manifest.chains.iter().map(|chain| &chain.name).collect();
+        assert_eq!(chains, ["j_0", "j_1"]);
+        for chain in &manifest.chains {
+            assert_eq!(chain.seed, Some(format!("{}_{}", seed, chain.name)));
+        }
+
+        let mut completed = manifest.completed.clone();
+        completed.sort_by(|a, b| {
+            (a.j_prime, a.temp)
+                .partial_cmp(&(b.j_prime, b.temp))
+                .unwrap()
+        });
+        let found: Vec<_> = completed.iter().map(|p| (p.j_prime, p.temp)).collect();
+        assert_eq!(found, [(0.0, 1.0), (0.0, 2.0), (1.0, 1.0), (1.0, 2.0)]);
+        for file in [
+            format!("csv/{}.csv", run),
+            format!("models/{}/config.toml", run),
+            format!("models/{}/j_1_t_1.bin", run),
+            format!("mmcif/{}/j_0_t_2.mmcif", run),
+        ] {
+            assert!(manifest.files.contains(&file), "{}", file);
+        }
+    }
+}
@@ -1,23 +1,23 @@
 use nalgebra::Vector3;
-use std::{collections::HashMap, fs::File, io::Write, path::Path, usize};
+use std::{collections::HashMap, fs::File, io::Write, path::Path};
 
 use crate::array3d::Array3d;
 
 /// A struct containing all information required to write a mmcif file
-struct MmCifWriter<'a, const S: usize> {
+struct MmCifWriter<'a> {
     cell_a: f32,
     cell_b: f32,
     cell_c: f32,
     naming: HashMap<i8, Option<Ion>>,
-    grid: &'a Array3d<i8, S, S, S>,
+    grid: &'a Array3d<i8>,
     file: File,
     counter: u32,
 }
 
-impl<'a, const S: usize> MmCifWriter<'a, S> {
+impl<'a> MmCifWriter<'a> {
     /// Constructor
     fn new(
-        grid: &'a Array3d<i8, S, S, S>,
+        grid: &'a Array3d<i8>,
         cell_a: f32,
         cell_b: f32,
         cell_c: f32,
@@ -36,7 +36,7 @@ impl<'a, const S: usize> MmCifWriter<'a, S> {
     }
 }
 
-impl<const S: usize> MmCifWriter<'_, S> {
+impl MmCifWriter<'_> {
     /// Make the Header for the mmcif file
     fn get_header(&self) -> String {
         format!(
@@ -72,7 +72,7 @@ _chem_comp.type
 
     fn get_symbols(&self) -> Vec<String> {
         let mut vec = Vec::new();
-        for ion in self.naming.values().into_iter().filter_map(|x| x.as_ref()) {
+        for ion in self.naming.values().filter_map(|x| x.as_ref()) {
             vec.append(&mut ion.get_uppercase_names())
         }
         vec
@@ -116,9 +116,10 @@ _atom_site.auth_seq_id
 _atom_site.auth_asym_id
 _atom_site.pdbx_PDB_model_num"
         )?;
-        for i in 0..(S as isize) {
-            for j in 0..(S as isize) {
-                for k in 0..(S as isize) {
+        let (width, height, depth) = self.grid.dims();
+        for i in 0..(width as isize) {
+            for j in 0..(height as isize) {
+                for k in 0..(depth as isize) {
                     let val = self.grid[(i, j, k)];
                     match self.naming.get(&val) {
                         Some(opt) => {
@@ -185,10 +186,11 @@ _atom_site.pdbx_PDB_model_num"
 
     /// convert an index to coordinates in armstong
     fn index_to_armstong(&self, i: isize, j: isize, k: isize) -> Vector3<f32> {
+        let (width, height, depth) = self.grid.dims();
         [
-            i as f32 / S as f32 * self.cell_a, // TODO check this logic!!!
-            j as f32 / S as f32 * self.cell_b,
-            k as f32 / S as f32 * self.cell_c,
+            i as f32 / width as f32 * self.cell_a, // TODO check this logic!!!
+            j as f32 / height as f32 * self.cell_b,
+            k as f32 / depth as f32 * self.cell_c,
         ]
         .into()
     }
@@ -220,8 +222,8 @@ impl Ion {
 /// Note that $\alpha = \beta = \gamma = 90 \degrees$
 /// The naming provides a translation from i8 to an ion
 /// If the ion is None it is just ignored.
-pub fn write_mmcif<const S: usize>(
-    grid: &Array3d<i8, S, S, S>,
+pub fn write_mmcif(
+    grid: &Array3d<i8>,
     cell_a: f32,
     cell_b: f32,
     cell_c: f32,
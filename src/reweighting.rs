@@ -0,0 +1,339 @@
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+
+use crate::wang_landau::log_sum_exp;
+use crate::{DensityOfStates, LineReader, Model, ModelError};
+
+/// The histogram of the neighbour sums of all shells sampled at a single temperature and couplings.
+/// As the energy only depends on the neighbour sums, the histogram can be reweighted
+/// to other temperatures and couplings, as long as the composition is fixed.
+#[derive(Clone, Debug)]
+pub struct NeighbourHistogram {
+    /// The interaction energies of the shells the histogram was sampled with
+    couplings: Vec<f32>,
+    temp: f64,
+    /// The number of cyanometalate sites
+    sites: usize,
+    /// The number of cyanometalates
+    metalates: usize,
+    /// The number of samples of every state
+    counts: HashMap<Vec<i64>, u64>,
+}
+
+impl NeighbourHistogram {
+    /// Creates an empty histogram for the couplings and composition of the model
+    pub fn new(model: &Model, temp: f32) -> Self {
+        Self {
+            couplings: model.hamiltonian().couplings().to_vec(),
+            temp: temp as f64,
+            sites: model.sites(),
+            metalates: model.metalates(),
+            counts: HashMap::new(),
+        }
+    }
+
+    /// Adds the current state of the model
+    pub fn add(&mut self, model: &Model) {
+        *self
+            .counts
+            .entry(model.hamiltonian().neighbour_sums().to_vec())
+            .or_insert(0) += 1;
+    }
+
+    /// Getter function for the couplings the histogram was sampled with
+    pub fn couplings(&self) -> &[f32] {
+        &self.couplings
+    }
+
+    /// Getter function for the temperature the histogram was sampled at
+    pub fn temp(&self) -> f64 {
+        self.temp
+    }
+
+    /// Getter function for the number of cyanometalate sites
+    pub fn sites(&self) -> usize {
+        self.sites
+    }
+
+    /// Getter function for the number of cyanometalates
+    pub fn metalates(&self) -> usize {
+        self.metalates
+    }
+
+    /// The total number of samples
+    pub fn samples(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    /// The energy of a state with the given neighbour sums for the couplings of the histogram
+    fn energy(&self, sums: &[i64]) -> f64 {
+        self.couplings
+            .iter()
+            .zip(sums)
+            .map(|(j, sum)| *j as f64 * *sum as f64)
+            .sum()
+    }
+
+    /// Writes the histogram to a csv file, the states are sorted by their neighbour sums
+    pub fn write_to_csv(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        writeln!(file, "{} temperature", self.temp)?;
+        writeln!(file, "{} sites", self.sites)?;
+        writeln!(file, "{} metalates", self.metalates)?;
+        let couplings: Vec<String> = self.couplings.iter().map(|j| j.to_string()).collect();
+        writeln!(file, "{} couplings", couplings.join(","))?;
+        let mut columns: Vec<String> = (1..=self.couplings.len())
+            .map(|n| format!("n_{}", n))
+            .collect();
+        columns.push("count".to_string());
+        writeln!(file, "{}", columns.join(","))?;
+        let mut states: Vec<_> = self.counts.iter().collect();
+        states.sort();
+        for (sums, count) in states {
+            for sum in sums {
+                write!(file, "{},", sum)?;
+            }
+            writeln!(file, "{}", count)?;
+        }
+        file.flush()
+    }
+
+    /// Reads a histogram written by write_to_csv
+    pub fn from_csv(path: impl AsRef<Path>) -> Result<Self, ModelError> {
+        let string = std::fs::read_to_string(path)?;
+        let mut reader = LineReader::new(&string);
+        let temp = reader.parse_next("temperature")?;
+        let sites = reader.parse_next("sites")?;
+        let metalates = reader.parse_next("metalates")?;
+        let couplings = reader.parse_next::<String>("couplings")?;
+        let couplings = couplings
+            .split(',')
+            .map(|j| {
+                j.parse().map_err(|_| ModelError::InvalidField {
+                    line: reader.line,
+                    field: "couplings",
+                    value: j.to_string(),
+                })
+            })
+            .collect::<Result<Vec<f32>, _>>()?;
+        reader.next_line("column names")?;
+        let mut counts = HashMap::new();
+        while let Ok(line) = reader.next_line("histogram") {
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = |value: &str| ModelError::InvalidField {
+                line: reader.line,
+                field: "histogram",
+                value: value.to_string(),
+            };
+            let values = line
+                .split(',')
+                .map(|s| s.parse().map_err(|_| invalid(s)))
+                .collect::<Result<Vec<i64>, _>>()?;
+            if values.len() != couplings.len() + 1 {
+                return Err(invalid(line));
+            }
+            let (sums, count) = values.split_at(couplings.len());
+            counts.insert(sums.to_vec(), count[0] as u64);
+        }
+        Ok(Self {
+            couplings,
+            temp,
+            sites,
+            metalates,
+            counts,
+        })
+    }
+}
+
+/// Combines histograms sampled at different temperatures and couplings into a single
+/// estimate of the density of states with the multiple histogram method of
+/// Ferrenberg and Swendsen (1989), which weights every histogram by its number of samples.
+/// The free energies of the histograms are iterated until they change less than tolerance.
+/// A single histogram is reweighted directly.
+/// All histograms need the same composition and the same shells,
+/// and neighbouring histograms need to overlap for the estimate to be reliable.
+pub fn ferrenberg_swendsen(
+    histograms: &[NeighbourHistogram],
+    tolerance: f64,
+    max_iterations: usize,
+) -> Result<DensityOfStates, String> {
+    let first = histograms.first().ok_or("there are no histograms")?;
+    if let Some(other) = histograms.iter().find(|histogram| {
+        (
+            histogram.sites,
+            histogram.metalates,
+            histogram.couplings.len(),
+        ) != (first.sites, first.metalates, first.couplings.len())
+    }) {
+        return Err(format!(
+            "the histograms have different compositions or shells: {} of {} sites with {} shells and {} of {} sites with {} shells",
+            first.metalates,
+            first.sites,
+            first.couplings.len(),
+            other.metalates,
+            other.sites,
+            other.couplings.len()
+        ));
+    }
+
+    let mut total: HashMap<&Vec<i64>, u64> = HashMap::new();
+    for histogram in histograms {
+        for (sums, count) in &histogram.counts {
+            *total.entry(sums).or_insert(0) += count;
+        }
+    }
+    // the reduced energies beta_i * E_i of every state in every histogram
+    let states: Vec<(&Vec<i64>, f64, Vec<f64>)> = total
+        .into_iter()
+        .map(|(sums, count)| {
+            let reduced = histograms
+                .iter()
+                .map(|histogram| histogram.energy(sums) / histogram.temp)
+                .collect();
+            (sums, (count as f64).ln(), reduced)
+        })
+        .collect();
+    let ln_samples: Vec<f64> = histograms
+        .iter()
+        .map(|histogram| (histogram.samples() as f64).ln())
+        .collect();
+
+    let ln_g = |free_energies: &[f64]| -> Vec<f64> {
+        states
+            .par_iter()
+            .map(|(_, ln_count, reduced)| {
+                ln_count
+                    - log_sum_exp(
+                        ln_samples
+                            .iter()
+                            .zip(free_energies)
+                            .zip(reduced)
+                            .map(|((ln_n, f), e)| ln_n + f - e),
+                    )
+            })
+            .collect()
+    };
+    let mut free_energies = vec![0.0; histograms.len()];
+    let mut converged = histograms.len() == 1;
+    for _ in 0..max_iterations {
+        if converged {
+            break;
+        }
+        let ln_g = ln_g(&free_energies);
+        let mut next: Vec<f64> = (0..histograms.len())
+            .into_par_iter()
+            .map(|i| {
+                -log_sum_exp(
+                    states
+                        .iter()
+                        .zip(&ln_g)
+                        .map(|((_, _, reduced), ln_g)| ln_g - reduced[i]),
+                )
+            })
+            .collect();
+        // the free energies are only defined up to a constant
+        let shift = next[0];
+        next.iter_mut().for_each(|f| *f -= shift);
+        converged = next
+            .iter()
+            .zip(&free_energies)
+            .all(|(new, old)| (new - old).abs() < tolerance);
+        free_energies = next;
+    }
+    if !converged {
+        return Err(format!(
+            "the free energies did not converge in {} iterations",
+            max_iterations
+        ));
+    }
+    Ok(DensityOfStates::new(
+        states
+            .iter()
+            .map(|(sums, _, _)| sums.to_vec())
+            .zip(ln_g(&free_energies))
+            .collect(),
+        first.sites,
+        first.metalates,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The degeneracies of the neighbour sums 0 to 3 of a single shell,
+    /// normalized like DensityOfStates to the 8 ways of placing 1 cyanometalate on 8 sites
+    const DEGENERACIES: [f64; 4] = [1.0, 3.0, 3.0, 1.0];
+
+    /// A histogram with the expected counts of the density of states at temp
+    fn exact_histogram(temp: f64, samples: f64) -> NeighbourHistogram {
+        let weights: Vec<f64> = DEGENERACIES
+            .iter()
+            .enumerate()
+            .map(|(n, g)| g * (-(n as f64) / temp).exp())
+            .collect();
+        let z: f64 = weights.iter().sum();
+        NeighbourHistogram {
+            couplings: vec![1.0],
+            temp,
+            sites: 8,
+            metalates: 1,
+            counts: weights
+                .iter()
+                .enumerate()
+                .map(|(n, weight)| (vec![n as i64], (samples * weight / z).round() as u64))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn recovers_the_density_of_states() {
+        let histograms = [
+            exact_histogram(0.5, 1e12),
+            exact_histogram(1.0, 2e12),
+            exact_histogram(3.0, 1e12),
+        ];
+        let density = ferrenberg_swendsen(&histograms, 1e-10, 10_000).unwrap();
+        let ln_g: Vec<f64> = density.ln_g().iter().map(|(_, ln_g)| *ln_g).collect();
+        assert_eq!(
+            density
+                .ln_g()
+                .iter()
+                .map(|(sums, _)| sums[0])
+                .collect::<Vec<_>>(),
+            [0, 1, 2, 3]
+        );
+        for (ln_g, g) in ln_g.iter().zip(DEGENERACIES) {
+            assert!((ln_g - g.ln()).abs() < 1e-6, "{} != {}", ln_g, g.ln());
+        }
+
+        // reweighted to a temperature between the histograms
+        let temp = 2.0;
+        let weights = DEGENERACIES
+            .iter()
+            .enumerate()
+            .map(|(n, g)| (n as f64, g * (-(n as f64) / temp).exp()));
+        let z: f64 = weights.clone().map(|(_, weight)| weight).sum();
+        let energy: f64 = weights.map(|(n, weight)| n * weight).sum::<f64>() / z;
+        let thermo = density.thermodynamics(&[1.0], temp);
+        assert!((thermo.energy - energy).abs() < 1e-6);
+        assert!((thermo.free_energy + temp * z.ln()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn reports_missing_convergence() {
+        let histograms = [exact_histogram(0.5, 1e6), exact_histogram(3.0, 1e6)];
+        assert!(ferrenberg_swendsen(&histograms, 1e-10, 1).is_err());
+    }
+
+    #[test]
+    fn rejects_different_shells() {
+        let mut other = exact_histogram(1.0, 1e6);
+        other.couplings.push(0.5);
+        assert!(ferrenberg_swendsen(&[exact_histogram(0.5, 1e6), other], 1e-10, 100).is_err());
+    }
+}
@@ -0,0 +1,490 @@
+use rayon::prelude::*;
+use rustfft::{num_complex::Complex, FftDirection, FftPlanner};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+use crate::{Array3d, DeltaPdf, Ion, YellVolume};
+
+/// The Cromer-Mann coefficients a_1, b_1, ..., a_4, b_4, c of the atomic form factors
+/// from the International Tables for Crystallography Vol. C, Table 6.1.1.4
+fn cromer_mann(name: &str) -> Option<[f64; 9]> {
+    match name {
+        "C" => Some([
+            2.31, 20.8439, 1.02, 10.2075, 1.5886, 0.5687, 0.865, 51.6512, 0.2156,
+        ]),
+        "N" => Some([
+            12.2126, 0.0057, 3.1322, 9.8933, 2.0125, 28.9975, 1.1663, 0.5826, -11.529,
+        ]),
+        "Mn" => Some([
+            11.2819, 5.3409, 7.3573, 0.3432, 3.0193, 17.8674, 2.2441, 83.7543, 1.0896,
+        ]),
+        "Co" => Some([
+            12.2841, 4.2791, 7.3409, 0.2784, 4.0034, 13.5359, 2.3488, 71.1692, 1.0118,
+        ]),
+        _ => None,
+    }
+}
+
+/// The atomic form factor at s = sin(theta) / lambda in inverse Ångström
+fn form_factor(coefficients: &[f64; 9], s: f64) -> f64 {
+    let s_sq = s * s;
+    coefficients[..8]
+        .chunks(2)
+        .map(|ab| ab[0] * (-ab[1] * s_sq).exp())
+        .sum::<f64>()
+        + coefficients[8]
+}
+
+/// The scattering of an ion placed at the origin, with the form factors of its atoms
+enum Scatterer {
+    Singlet([f64; 9]),
+    Cyanometalate {
+        metal: [f64; 9],
+        carbon: [f64; 9],
+        nitrogen: [f64; 9],
+        c_offset: f64,
+        n_offset: f64,
+    },
+}
+
+impl Scatterer {
+    fn new(ion: &Ion) -> Result<Self, String> {
+        let coefficients =
+            |name: &str| cromer_mann(name).ok_or(format!("no form factor for {}", name));
+        Ok(match *ion {
+            Ion::Singlet(name) => Scatterer::Singlet(coefficients(name)?),
+            Ion::Cyanometalate {
+                name,
+                c_offset,
+                n_offset,
+            } => Scatterer::Cyanometalate {
+                metal: coefficients(name)?,
+                carbon: coefficients("C")?,
+                nitrogen: coefficients("N")?,
+                c_offset: c_offset as f64,
+                n_offset: n_offset as f64,
+            },
+        })
+    }
+
+    /// The structure factor of the ion for the scattering vector q in inverse Ångström.
+    /// It is real, as the cyanides are placed symmetrically around the metal
+    fn structure_factor(&self, q: [f64; 3]) -> f64 {
+        let s = (q[0] * q[0] + q[1] * q[1] + q[2] * q[2]).sqrt() / 2.0;
+        match self {
+            Scatterer::Singlet(coefficients) => form_factor(coefficients, s),
+            Scatterer::Cyanometalate {
+                metal,
+                carbon,
+                nitrogen,
+                c_offset,
+                n_offset,
+            } => {
+                // the ligands at +-offset along the three axes
+                let ligands = |offset: f64| -> f64 {
+                    q.iter().map(|q| 2.0 * (2.0 * PI * q * offset).cos()).sum()
+                };
+                form_factor(metal, s)
+                    + form_factor(carbon, s) * ligands(*c_offset)
+                    + form_factor(nitrogen, s) * ligands(*n_offset)
+            }
+        }
+    }
+}
+
+/// The intensity |F(hkl)|^2 of a model on the reciprocal lattice of the whole model,
+/// so h, k and l are given in units of the reciprocal lengths of the model.
+/// Bragg peaks of the average structure lie at multiples of the number of unit cells.
+/// The intensities of several snapshots of a model can be averaged with merge.
+#[derive(Clone, Debug)]
+pub struct DiffuseScattering {
+    /// The intensities, the first index along every axis corresponds to -max
+    intensities: Array3d<f64>,
+    /// The largest h, k and l
+    max: (usize, usize, usize),
+    /// The number of configurations the intensities are averaged over
+    snapshots: u64,
+}
+
+impl DiffuseScattering {
+    /// Getter function for the intensities, the first index along every axis corresponds to -max
+    pub fn intensities(&self) -> &Array3d<f64> {
+        &self.intensities
+    }
+
+    /// Getter function for the largest h, k and l
+    pub fn max(&self) -> (usize, usize, usize) {
+        self.max
+    }
+
+    /// Getter function for the number of configurations the intensities are averaged over
+    pub fn snapshots(&self) -> u64 {
+        self.snapshots
+    }
+
+    /// Combines the averages of both, which need to have the same largest hkl
+    pub fn merge(&mut self, other: &DiffuseScattering) {
+        assert_eq!(self.max, other.max);
+        let snapshots = self.snapshots + other.snapshots;
+        let weight = other.snapshots as f64 / snapshots as f64;
+        for (intensity, other) in self
+            .intensities
+            .as_flat_slice_mut()
+            .iter_mut()
+            .zip(other.intensities.as_flat_slice())
+        {
+            *intensity += (other - *intensity) * weight;
+        }
+        self.snapshots = snapshots;
+    }
+
+    /// The intensity at hkl, which need to be between -max and max
+    pub fn intensity(&self, h: isize, k: isize, l: isize) -> f64 {
+        let (max_h, max_k, max_l) = self.max;
+        assert!(
+            h.unsigned_abs() <= max_h && k.unsigned_abs() <= max_k && l.unsigned_abs() <= max_l
+        );
+        self.intensities[(h + max_h as isize, k + max_k as isize, l + max_l as isize)]
+    }
+
+    /// The intensities as a Yell volume in units of the average unit cell, of which there
+    /// are the given number of supercells along a, b and c in the model
+    pub fn to_yell(
+        &self,
+        supercells: (usize, usize, usize),
+        unit_cell: [f64; 6],
+    ) -> YellVolume<'_> {
+        let (max_h, max_k, max_l) = self.max;
+        let (a, b, c) = supercells;
+        YellVolume {
+            data: &self.intensities,
+            lower_limits: [
+                -(max_h as f64) / a as f64,
+                -(max_k as f64) / b as f64,
+                -(max_l as f64) / c as f64,
+            ],
+            step_sizes: [1.0 / a as f64, 1.0 / b as f64, 1.0 / c as f64],
+            unit_cell,
+            is_direct: false,
+        }
+    }
+
+    /// The 3D-ΔPDF of intensities calculated by difference_scattering,
+    /// the Fourier transform of the intensities between -max and max
+    pub fn delta_pdf(&self) -> DeltaPdf {
+        DeltaPdf::new(&self.intensities, self.max)
+    }
+}
+
+/// Calculates the scattered intensity of the grid, where the ion at every grid point
+/// is given by the naming as in the mmcif files, for all hkl between -max and max.
+/// cell_a, cell_b and cell_c are the lengths of the whole grid in Ångström.
+/// For every kind of ion the lattice sum over its sites is calculated with a FFT of the grid,
+/// which is periodic on the reciprocal lattice, and multiplied by the structure factor of the ion.
+/// Thermal motion is neglected.
+pub fn diffuse_scattering(
+    grid: &Array3d<i8>,
+    cell_a: f32,
+    cell_b: f32,
+    cell_c: f32,
+    naming: &HashMap<i8, Option<Ion>>,
+    max: (usize, usize, usize),
+) -> Result<DiffuseScattering, String> {
+    scattering(grid, (cell_a, cell_b, cell_c), naming, max, false)
+}
+
+/// Calculates the scattered intensity of the grid without the Bragg peaks of its average structure,
+/// which is the average of the occupations over all unit cells of two grid points along every axis.
+/// The amplitude is the one of the deviations of the occupations from the average structure,
+/// so the intensity is the total intensity minus the Bragg intensity of the average structure.
+/// The arguments are the same as for diffuse_scattering
+pub fn difference_scattering(
+    grid: &Array3d<i8>,
+    cell_a: f32,
+    cell_b: f32,
+    cell_c: f32,
+    naming: &HashMap<i8, Option<Ion>>,
+    max: (usize, usize, usize),
+) -> Result<DiffuseScattering, String> {
+    scattering(grid, (cell_a, cell_b, cell_c), naming, max, true)
+}
+
+/// The intensity of the occupations of every ion, or of their deviations from the average structure
+fn scattering(
+    grid: &Array3d<i8>,
+    (cell_a, cell_b, cell_c): (f32, f32, f32),
+    naming: &HashMap<i8, Option<Ion>>,
+    max: (usize, usize, usize),
+    difference: bool,
+) -> Result<DiffuseScattering, String> {
+    let (width, height, depth) = grid.dims();
+    // the position of a grid point in its unit cell
+    let position = |index: usize| {
+        let (x, y, z) = (
+            index % width,
+            (index / width) % height,
+            index / (width * height),
+        );
+        (x % 2) + 2 * (y % 2) + 4 * (z % 2)
+    };
+    let mut lattice_sums = Vec::new();
+    for (value, ion) in naming {
+        if let Some(ion) = ion {
+            let mut sites = Array3d::<Complex<f64>>::new(width, height, depth);
+            for (site, occupied) in sites
+                .as_flat_slice_mut()
+                .iter_mut()
+                .zip(grid.as_flat_slice())
+            {
+                if occupied == value {
+                    *site = Complex::new(1.0, 0.0);
+                }
+            }
+            if difference {
+                let mut average = [0.0; 8];
+                for (index, site) in sites.as_flat_slice().iter().enumerate() {
+                    average[position(index)] += site.re;
+                }
+                let cells = (width * height * depth / 8) as f64;
+                for (index, site) in sites.as_flat_slice_mut().iter_mut().enumerate() {
+                    site.re -= average[position(index)] / cells;
+                }
+            }
+            fft_3d(&mut sites, FftDirection::Forward);
+            lattice_sums.push((Scatterer::new(ion)?, sites));
+        }
+    }
+
+    let (max_h, max_k, max_l) = max;
+    let dims = (2 * max_h + 1, 2 * max_k + 1, 2 * max_l + 1);
+    let mut intensities = Array3d::new(dims.0, dims.1, dims.2);
+    // the flat storage runs fastest along h, so every chunk is a line along h
+    intensities
+        .as_flat_slice_mut()
+        .par_chunks_mut(dims.0)
+        .enumerate()
+        .for_each(|(line, intensities)| {
+            let k = (line % dims.1) as isize - max_k as isize;
+            let l = (line / dims.1) as isize - max_l as isize;
+            for (i, intensity) in intensities.iter_mut().enumerate() {
+                let h = i as isize - max_h as isize;
+                let q = [
+                    h as f64 / cell_a as f64,
+                    k as f64 / cell_b as f64,
+                    l as f64 / cell_c as f64,
+                ];
+                let amplitude: Complex<f64> = lattice_sums
+                    .iter()
+                    .map(|(scatterer, sums)| sums[(h, k, l)] * scatterer.structure_factor(q))
+                    .sum();
+                *intensity = amplitude.norm_sqr();
+            }
+        });
+    Ok(DiffuseScattering {
+        intensities,
+        max,
+        snapshots: 1,
+    })
+}
+
+/// The unnormalized discrete Fourier transform of the grid along all three axes
+pub(crate) fn fft_3d(data: &mut Array3d<Complex<f64>>, direction: FftDirection) {
+    let (width, height, depth) = data.dims();
+    let mut planner = FftPlanner::new();
+    let values = data.as_flat_slice_mut();
+    // the lines along a are contiguous and transformed at once
+    planner.plan_fft(width, direction).process(values);
+    // the lines along b and c are gathered into a buffer
+    let axes = [
+        (
+            height,
+            width,
+            (0..depth * width)
+                .map(|i| (i / width) * width * height + i % width)
+                .collect::<Vec<_>>(),
+        ),
+        (depth, width * height, (0..width * height).collect()),
+    ];
+    for (len, stride, starts) in axes {
+        let fft = planner.plan_fft(len, direction);
+        let mut line = vec![Complex::default(); len];
+        for start in starts {
+            for (n, value) in line.iter_mut().enumerate() {
+                *value = values[start + n * stride];
+            }
+            fft.process(&mut line);
+            for (n, value) in line.iter().enumerate() {
+                values[start + n * stride] = *value;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{Model, PairHamiltonian};
+
+    /// The occupations of the grid by every ion of the naming
+    pub(crate) fn occupations(
+        grid: &Array3d<i8>,
+        naming: &HashMap<i8, Option<Ion>>,
+    ) -> Vec<(Ion, Array3d<f64>)> {
+        let (width, height, depth) = grid.dims();
+        naming
+            .iter()
+            .filter_map(|(value, ion)| {
+                let mut occupations = Array3d::new(width, height, depth);
+                for (occupation, site) in occupations
+                    .as_flat_slice_mut()
+                    .iter_mut()
+                    .zip(grid.as_flat_slice())
+                {
+                    *occupation = if site == value { 1.0 } else { 0.0 };
+                }
+                ion.map(|ion| (ion, occupations))
+            })
+            .collect()
+    }
+
+    /// The amplitude at hkl as a direct sum over all atoms of the ions,
+    /// each weighted with the occupation of its grid point
+    pub(crate) fn direct_amplitude(
+        occupations: &[(Ion, Array3d<f64>)],
+        (cell_a, cell_b, cell_c): (f32, f32, f32),
+        (h, k, l): (isize, isize, isize),
+    ) -> Complex<f64> {
+        let cell = [cell_a as f64, cell_b as f64, cell_c as f64];
+        let q = [h as f64 / cell[0], k as f64 / cell[1], l as f64 / cell[2]];
+        let s = (q[0] * q[0] + q[1] * q[1] + q[2] * q[2]).sqrt() / 2.0;
+        let f = |name: &str| form_factor(&cromer_mann(name).unwrap(), s);
+        let mut amplitude = Complex::default();
+        for (ion, occupations) in occupations {
+            // the atoms of the ion with their offsets from the grid point in Ångström
+            let mut atoms = Vec::new();
+            match *ion {
+                Ion::Singlet(name) => atoms.push((f(name), [0.0; 3])),
+                Ion::Cyanometalate {
+                    name,
+                    c_offset,
+                    n_offset,
+                } => {
+                    atoms.push((f(name), [0.0; 3]));
+                    for (ligand, offset) in [("C", c_offset), ("N", n_offset)] {
+                        for axis in 0..3 {
+                            for sign in [-1.0, 1.0] {
+                                let mut position = [0.0; 3];
+                                position[axis] = sign * offset as f64;
+                                atoms.push((f(ligand), position));
+                            }
+                        }
+                    }
+                }
+            }
+            let (width, height, depth) = occupations.dims();
+            let grid_points = [width as f64, height as f64, depth as f64];
+            for x in 0..width {
+                for y in 0..height {
+                    for z in 0..depth {
+                        let occupation = occupations[(x as isize, y as isize, z as isize)];
+                        if occupation == 0.0 {
+                            continue;
+                        }
+                        let site = [x as f64, y as f64, z as f64];
+                        for (form_factor, offset) in &atoms {
+                            let phase: f64 = (0..3)
+                                .map(|i| {
+                                    2.0 * PI
+                                        * q[i]
+                                        * (site[i] / grid_points[i] * cell[i] + offset[i])
+                                })
+                                .sum();
+                            amplitude += Complex::from_polar(occupation * form_factor, phase);
+                        }
+                    }
+                }
+            }
+        }
+        amplitude
+    }
+
+    /// Asserts that the intensities agree with the direct sums at every hkl
+    pub(crate) fn assert_intensities(
+        scattering: &DiffuseScattering,
+        direct: impl Fn((isize, isize, isize)) -> f64,
+    ) {
+        let (max_h, max_k, max_l) = scattering.max();
+        let largest = scattering
+            .intensities()
+            .as_flat_slice()
+            .iter()
+            .fold(0.0, |largest: f64, intensity| largest.max(*intensity));
+        for h in -(max_h as isize)..=max_h as isize {
+            for k in -(max_k as isize)..=max_k as isize {
+                for l in -(max_l as isize)..=max_l as isize {
+                    let (fft, direct) = (scattering.intensity(h, k, l), direct((h, k, l)));
+                    assert!(
+                        (fft - direct).abs() <= 1e-9 * largest,
+                        "{} != {} at {:?}",
+                        fft,
+                        direct,
+                        (h, k, l)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fft_equals_direct_sum() {
+        let model = Model::new((4, 6, 8), vec![-1.0, 0.5], 0.5, Some("scattering")).unwrap();
+        let scattering = model.diffuse_scattering(1.5);
+        assert_eq!(scattering.max(), (3, 4, 6));
+        assert_eq!(scattering.snapshots(), 1);
+        let occupations = occupations(&model.grid, &Model::<PairHamiltonian>::naming());
+        assert_intensities(&scattering, |hkl| {
+            direct_amplitude(&occupations, model.cell(), hkl).norm_sqr()
+        });
+        // Friedel's law holds, and all grid points scatter in phase at the origin
+        let (friedel, mate) = (
+            scattering.intensity(1, -2, 3),
+            scattering.intensity(-1, 2, -3),
+        );
+        assert!((friedel - mate).abs() < 1e-9 * friedel);
+        let sites = model.sites() as f64;
+        let metalates = model.metalates() as f64;
+        let f = |name: &str| form_factor(&cromer_mann(name).unwrap(), 0.0);
+        let expected = sites * f("Mn") + metalates * (f("Co") + 6.0 * f("C") + 6.0 * f("N"));
+        let forward = scattering.intensity(0, 0, 0);
+        assert!((forward - expected * expected).abs() < 1e-9 * forward);
+    }
+
+    #[test]
+    fn snapshots_average_the_direct_sums() {
+        let models: Vec<_> = ["first", "second", "third"]
+            .into_iter()
+            .map(|seed| Model::new((4, 4, 4), vec![-1.0, 0.5], 0.5, Some(seed)).unwrap())
+            .collect();
+        assert_ne!(
+            models[0].grid.as_flat_slice(),
+            models[1].grid.as_flat_slice()
+        );
+        // the average of two snapshots merged with a single one weighs all three equally
+        let mut pair = models[0].diffuse_scattering(1.0);
+        pair.merge(&models[1].diffuse_scattering(1.0));
+        let mut scattering = models[2].diffuse_scattering(1.0);
+        scattering.merge(&pair);
+        assert_eq!(scattering.snapshots(), 3);
+        let occupations: Vec<_> = models
+            .iter()
+            .map(|model| occupations(&model.grid, &Model::<PairHamiltonian>::naming()))
+            .collect();
+        assert_intensities(&scattering, |hkl| {
+            occupations
+                .iter()
+                .map(|occupations| direct_amplitude(occupations, models[0].cell(), hkl).norm_sqr())
+                .sum::<f64>()
+                / 3.0
+        });
+    }
+}
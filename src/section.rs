@@ -0,0 +1,551 @@
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::{LineReader, ModelError, YellVolume};
+
+/// A plane through a volume spanned by the directions u and v through the origin,
+/// all given in the coordinates of the volume, for example hkl in units of the average unit cell.
+/// It is written like `hk0`, `h0.5l` or `hhl`, where every letter is a direction in the plane
+/// and the numbers are the coordinates of the origin, or as `u_1,u_2,u_3:v_1,v_2,v_3`
+/// with an optional origin `:o_1,o_2,o_3`
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Plane {
+    pub origin: [f64; 3],
+    pub u: [f64; 3],
+    pub v: [f64; 3],
+}
+
+impl Plane {
+    /// Checks that u and v span a plane
+    fn validate(self) -> Result<Self, String> {
+        let [a, b, c] = self.u;
+        let [d, e, f] = self.v;
+        let normal = [b * f - c * e, c * d - a * f, a * e - b * d];
+        if normal.iter().all(|x| *x == 0.0) {
+            return Err(format!("{} does not span a plane", self));
+        }
+        Ok(self)
+    }
+
+    /// The letters of the short form, if the directions are sums of distinct axes
+    /// and the origin is only set on the other axes
+    fn letters(&self) -> Option<[Option<char>; 3]> {
+        let mut letters = [None; 3];
+        for direction in [self.u, self.v] {
+            let axes: Vec<usize> = (0..3).filter(|i| direction[*i] != 0.0).collect();
+            let letter = ['h', 'k', 'l'][*axes.first()?];
+            for i in axes {
+                if direction[i] != 1.0 || letters[i].is_some() || self.origin[i] != 0.0 {
+                    return None;
+                }
+                letters[i] = Some(letter);
+            }
+        }
+        Some(letters)
+    }
+}
+
+impl std::fmt::Display for Plane {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.letters() {
+            Some(letters) => {
+                for (letter, origin) in letters.iter().zip(self.origin) {
+                    match letter {
+                        Some(letter) => write!(f, "{}", letter)?,
+                        None => write!(f, "{}", origin)?,
+                    }
+                }
+                Ok(())
+            }
+            None => {
+                let vector = |x: [f64; 3]| format!("{},{},{}", x[0], x[1], x[2]);
+                write!(f, "{}:{}", vector(self.u), vector(self.v))?;
+                if self.origin != [0.0; 3] {
+                    write!(f, ":{}", vector(self.origin))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Parses a plane like `hk0` or `1,1,0:0,0,1`
+impl FromStr for Plane {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("'{}' is not a plane like hk0 or 1,1,0:0,0,1", s);
+        if s.contains(':') {
+            let vector = |part: &str| -> Result<[f64; 3], String> {
+                let values = part
+                    .split(',')
+                    .map(|value| value.trim().parse::<f64>().map_err(|_| invalid()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                values.try_into().map_err(|_| invalid())
+            };
+            let parts: Vec<_> = s.split(':').collect();
+            let (u, v, origin) = match parts.as_slice() {
+                [u, v] => (vector(u)?, vector(v)?, [0.0; 3]),
+                [u, v, origin] => (vector(u)?, vector(v)?, vector(origin)?),
+                _ => return Err(invalid()),
+            };
+            return Plane { origin, u, v }.validate();
+        }
+        // every axis is either a letter or the number of the origin
+        let mut rest = s.trim();
+        let mut slots = Vec::new();
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix(['h', 'k', 'l']) {
+                slots.push(Err(rest.chars().next().unwrap()));
+                rest = after;
+            } else {
+                let len = rest.find(['h', 'k', 'l']).unwrap_or(rest.len());
+                slots.push(Ok(rest[..len].parse::<f64>().map_err(|_| invalid())?));
+                rest = &rest[len..];
+            }
+        }
+        if slots.len() != 3 {
+            return Err(invalid());
+        }
+        let mut directions: Vec<(char, [f64; 3])> = Vec::new();
+        let mut origin = [0.0; 3];
+        for (i, slot) in slots.into_iter().enumerate() {
+            match slot {
+                Ok(value) => origin[i] = value,
+                Err(letter) => match directions.iter_mut().find(|(l, _)| *l == letter) {
+                    Some((_, direction)) => direction[i] = 1.0,
+                    None => {
+                        let mut direction = [0.0; 3];
+                        direction[i] = 1.0;
+                        directions.push((letter, direction));
+                    }
+                },
+            }
+        }
+        match directions.as_slice() {
+            [(_, u), (_, v)] => Ok(Plane {
+                origin,
+                u: *u,
+                v: *v,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl TryFrom<String> for Plane {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Plane> for String {
+    fn from(plane: Plane) -> Self {
+        plane.to_string()
+    }
+}
+
+/// How the values of a section are mapped to gray levels, large values are dark
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rendering {
+    /// Whether the logarithm of the values is shown, values that are not positive are left out
+    pub log: bool,
+    /// The percentiles of the values that are clamped at the low and at the high end
+    /// like `clamp_array` in python
+    pub clamp: (f64, f64),
+}
+
+impl Default for Rendering {
+    fn default() -> Self {
+        Self {
+            log: true,
+            clamp: (0.0, 0.0),
+        }
+    }
+}
+
+/// A two dimensional section of a volume, the rows run along u and the columns along v.
+/// Points outside of the volume are NaN
+#[derive(Clone, Debug, PartialEq)]
+pub struct Section {
+    values: Vec<f64>,
+    rows: usize,
+    columns: usize,
+}
+
+impl Section {
+    /// Takes the values of the volume on the plane at the grid points closest to it.
+    /// The steps along u and v are the largest that do not skip grid points of the volume.
+    /// If symmetrize is set, every value is averaged over all points of the volume
+    /// that are equivalent under the cubic Laue group m-3m.
+    /// Fails if the plane does not pass through any point of the volume
+    pub fn new(volume: &YellVolume, plane: &Plane, symmetrize: bool) -> Result<Self, String> {
+        let plane = plane.validate()?;
+        let (width, height, depth) = volume.data.dims();
+        let dims = [width, height, depth];
+        let value_at = |x: [f64; 3]| -> Option<f64> {
+            let mut index = [0; 3];
+            for i in 0..3 {
+                let position = ((x[i] - volume.lower_limits[i]) / volume.step_sizes[i]).round();
+                if !(0.0..dims[i] as f64).contains(&position) {
+                    return None;
+                }
+                index[i] = position as isize;
+            }
+            let value = volume.data[(index[0], index[1], index[2])];
+            (!value.is_nan()).then_some(value)
+        };
+        let operations = symmetry_operations(symmetrize);
+        let value = |x: [f64; 3]| -> f64 {
+            let (sum, count) = operations
+                .iter()
+                .filter_map(|(permutation, signs)| {
+                    value_at([0, 1, 2].map(|i| signs[i] * x[permutation[i]]))
+                })
+                .fold((0.0, 0), |(sum, count), value| (sum + value, count + 1));
+            if count == 0 {
+                f64::NAN
+            } else {
+                sum / count as f64
+            }
+        };
+
+        // the step along a direction is one grid point along the axis where it is the largest
+        let step = |direction: [f64; 3]| -> ([f64; 3], isize) {
+            let scale = (0..3)
+                .map(|i| direction[i].abs() / volume.step_sizes[i])
+                .fold(0.0, f64::max);
+            let step = direction.map(|x| x / scale);
+            let extent = (0..3)
+                .filter(|i| step[*i] != 0.0)
+                .map(|i| dims[i] as f64 * volume.step_sizes[i] / step[i].abs())
+                .fold(0.0, f64::max);
+            (step, extent.ceil() as isize + 1)
+        };
+        let (du, extent_u) = step(plane.u);
+        let (dv, extent_v) = step(plane.v);
+        let mut values = Vec::new();
+        for i in -extent_u..=extent_u {
+            for j in -extent_v..=extent_v {
+                let x = [0, 1, 2].map(|n| plane.origin[n] + i as f64 * du[n] + j as f64 * dv[n]);
+                values.push(value(x));
+            }
+        }
+        let full = Self {
+            values,
+            rows: (2 * extent_u + 1) as usize,
+            columns: (2 * extent_v + 1) as usize,
+        };
+        full.crop().ok_or_else(|| {
+            format!(
+                "the plane {} does not pass through any point of the volume",
+                plane
+            )
+        })
+    }
+
+    /// The smallest section containing all values that are not NaN,
+    /// None if all values are NaN
+    fn crop(&self) -> Option<Self> {
+        let filled = |row: usize, column: usize| !self.values[row * self.columns + column].is_nan();
+        let rows: Vec<usize> = (0..self.rows)
+            .filter(|row| (0..self.columns).any(|column| filled(*row, column)))
+            .collect();
+        let columns: Vec<usize> = (0..self.columns)
+            .filter(|column| (0..self.rows).any(|row| filled(row, *column)))
+            .collect();
+        let (first_row, last_row) = (rows.first()?, rows.last()?);
+        let (first_column, last_column) = (columns.first()?, columns.last()?);
+        let mut values = Vec::new();
+        for row in *first_row..=*last_row {
+            let start = row * self.columns;
+            values.extend(&self.values[start + first_column..=start + last_column]);
+        }
+        Some(Self {
+            values,
+            rows: last_row - first_row + 1,
+            columns: last_column - first_column + 1,
+        })
+    }
+
+    /// Arranges sections in a grid, every tile is as large as the largest section
+    /// and missing sections are left empty
+    pub fn tile(tiles: &[Vec<Option<Section>>]) -> Self {
+        let sections = || tiles.iter().flatten().flatten();
+        let tile_rows = sections().map(|section| section.rows).max().unwrap_or(0);
+        let tile_columns = sections().map(|section| section.columns).max().unwrap_or(0);
+        let columns = tile_columns * tiles.iter().map(Vec::len).max().unwrap_or(0);
+        let rows = tile_rows * tiles.len();
+        let mut values = vec![f64::NAN; rows * columns];
+        for (y, tile_row) in tiles.iter().enumerate() {
+            for (x, section) in tile_row.iter().enumerate() {
+                let Some(section) = section else {
+                    continue;
+                };
+                for row in 0..section.rows {
+                    let start = (y * tile_rows + row) * columns + x * tile_columns;
+                    values[start..start + section.columns].copy_from_slice(
+                        &section.values[row * section.columns..(row + 1) * section.columns],
+                    );
+                }
+            }
+        }
+        Self {
+            values,
+            rows,
+            columns,
+        }
+    }
+
+    /// Getter function for the number of rows along u
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Getter function for the number of columns along v
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    /// The value at a row and column
+    pub fn value(&self, row: usize, column: usize) -> f64 {
+        assert!(row < self.rows && column < self.columns);
+        self.values[row * self.columns + column]
+    }
+
+    /// Writes the section as a gray scale png like `plt.imsave` with `cmap="gray_r"`,
+    /// the smallest shown value is white and the largest black. Left out values are transparent.
+    /// Fails for an empty section, e.g. a map without any section
+    pub fn write_png(&self, path: impl AsRef<Path>, rendering: &Rendering) -> std::io::Result<()> {
+        if self.rows == 0 || self.columns == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "the section of {}x{} pixels is empty",
+                    self.columns, self.rows
+                ),
+            ));
+        }
+        let values: Vec<f64> = self
+            .values
+            .iter()
+            .map(|value| match rendering.log {
+                true if *value > 0.0 => value.ln(),
+                true => f64::NAN,
+                false => *value,
+            })
+            .collect();
+        let mut sorted: Vec<f64> = values.iter().copied().filter(|x| !x.is_nan()).collect();
+        sorted.sort_by(f64::total_cmp);
+        let (min, max) = match (sorted.first(), sorted.last()) {
+            (Some(_), Some(_)) => (
+                percentile(&sorted, rendering.clamp.0),
+                percentile(&sorted, 100.0 - rendering.clamp.1),
+            ),
+            _ => (0.0, 0.0),
+        };
+        let mut pixels = Vec::with_capacity(2 * values.len());
+        for value in values {
+            if value.is_nan() {
+                pixels.extend([0, 0]);
+            } else {
+                let scaled = if max > min {
+                    ((value - min) / (max - min)).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                pixels.extend([(255.0 * (1.0 - scaled)).round() as u8, 255]);
+            }
+        }
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        let mut encoder = png::Encoder::new(file, self.columns as u32, self.rows as u32);
+        encoder.set_color(png::ColorType::GrayscaleAlpha);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(std::io::Error::other)?;
+        writer
+            .write_image_data(&pixels)
+            .map_err(std::io::Error::other)?;
+        writer.finish().map_err(std::io::Error::other)
+    }
+
+    /// Writes the values to a csv file, one line per row
+    pub fn write_to_csv(&self, path: impl AsRef<Path>, plane: &Plane) -> std::io::Result<()> {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        writeln!(file, "{} plane", plane)?;
+        writeln!(file, "{} rows", self.rows)?;
+        writeln!(file, "{} columns", self.columns)?;
+        for row in self.values.chunks(self.columns.max(1)) {
+            let row: Vec<String> = row.iter().map(|value| value.to_string()).collect();
+            writeln!(file, "{}", row.join(","))?;
+        }
+        file.flush()
+    }
+
+    /// Reads a section written by write_to_csv
+    pub fn from_csv(path: impl AsRef<Path>) -> Result<Self, ModelError> {
+        let string = std::fs::read_to_string(path)?;
+        let mut reader = LineReader::new(&string);
+        reader.parse_next::<String>("plane")?;
+        let rows = reader.parse_next("rows")?;
+        let columns = reader.parse_next("columns")?;
+        if rows == 0 || columns == 0 {
+            return Err(ModelError::InvalidField {
+                line: reader.line,
+                field: "columns",
+                value: format!("{} rows and {} columns", rows, columns),
+            });
+        }
+        let mut values = Vec::with_capacity(rows * columns);
+        for _ in 0..rows {
+            let line = reader.next_line("section")?;
+            for value in line.split(',') {
+                values.push(value.parse().map_err(|_| ModelError::InvalidField {
+                    line: reader.line,
+                    field: "section",
+                    value: value.to_string(),
+                })?);
+            }
+        }
+        if values.len() != rows * columns {
+            return Err(ModelError::InvalidField {
+                line: reader.line,
+                field: "section",
+                value: format!("{} values instead of {}", values.len(), rows * columns),
+            });
+        }
+        Ok(Self {
+            values,
+            rows,
+            columns,
+        })
+    }
+}
+
+/// The permutations and sign changes of the axes of m-3m, or only the identity
+fn symmetry_operations(symmetrize: bool) -> Vec<([usize; 3], [f64; 3])> {
+    if !symmetrize {
+        return vec![([0, 1, 2], [1.0; 3])];
+    }
+    let permutations = [
+        [0, 1, 2],
+        [0, 2, 1],
+        [1, 0, 2],
+        [1, 2, 0],
+        [2, 0, 1],
+        [2, 1, 0],
+    ];
+    let mut operations = Vec::new();
+    for permutation in permutations {
+        for signs in 0..8 {
+            let sign = |bit: usize| if signs & (1 << bit) == 0 { 1.0 } else { -1.0 };
+            operations.push((permutation, [sign(0), sign(1), sign(2)]));
+        }
+    }
+    operations
+}
+
+/// The percentile of sorted values with linear interpolation like `np.percentile`
+fn percentile(sorted: &[f64], percentile: f64) -> f64 {
+    let rank = percentile.clamp(0.0, 100.0) / 100.0 * (sorted.len() - 1) as f64;
+    let below = rank.floor() as usize;
+    let above = rank.ceil() as usize;
+    sorted[below] + (sorted[above] - sorted[below]) * (rank - below as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Array3d;
+
+    fn plane(origin: [f64; 3], u: [f64; 3], v: [f64; 3]) -> Plane {
+        Plane { origin, u, v }
+    }
+
+    #[test]
+    fn parses_planes() {
+        let x = [1.0, 0.0, 0.0];
+        let y = [0.0, 1.0, 0.0];
+        let z = [0.0, 0.0, 1.0];
+        let cases = [
+            ("hk0", plane([0.0; 3], x, y)),
+            ("h0.5l", plane([0.0, 0.5, 0.0], x, z)),
+            ("-1kl", plane([-1.0, 0.0, 0.0], y, z)),
+            ("hhl", plane([0.0; 3], [1.0, 1.0, 0.0], z)),
+            ("1,1,0:0,0,1", plane([0.0; 3], [1.0, 1.0, 0.0], z)),
+            (
+                "1,2,0:0,0,1:0.5,0,0",
+                plane([0.5, 0.0, 0.0], [1.0, 2.0, 0.0], z),
+            ),
+        ];
+        for (string, expected) in cases {
+            assert_eq!(string.parse::<Plane>(), Ok(expected), "{}", string);
+        }
+    }
+
+    #[test]
+    fn displays_planes_in_the_short_form_if_possible() {
+        for (string, display) in [
+            ("hk0", "hk0"),
+            ("h0.5l", "h0.5l"),
+            ("1,1,0:0,0,1", "hhl"),
+            ("1,2,0:0,0,1", "1,2,0:0,0,1"),
+            ("1,0,0:0,1,0:0,0,2", "hk2"),
+            ("1,1,0:0,0,1:1,0,0", "1,1,0:0,0,1:1,0,0"),
+        ] {
+            let plane: Plane = string.parse().unwrap();
+            assert_eq!(plane.to_string(), display);
+            assert_eq!(display.parse::<Plane>(), Ok(plane));
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_planes() {
+        for string in [
+            "",
+            "hk",
+            "hhh",
+            "hkl",
+            "hk0l",
+            "hkx",
+            "1,0,0",
+            "1,0:0,1",
+            "1,0,0:2,0,0",
+        ] {
+            assert!(string.parse::<Plane>().is_err(), "{}", string);
+        }
+    }
+
+    #[test]
+    fn section_of_a_volume() {
+        let mut data = Array3d::new(3, 3, 3);
+        for i in 0..3 {
+            for j in 0..3 {
+                for k in 0..3 {
+                    data[(i, j, k)] = (9 * i + 3 * j + k) as f64;
+                }
+            }
+        }
+        let volume = YellVolume {
+            data: &data,
+            lower_limits: [-1.0; 3],
+            step_sizes: [1.0; 3],
+            unit_cell: [10.0, 10.0, 10.0, 90.0, 90.0, 90.0],
+            is_direct: false,
+        };
+        let section = Section::new(&volume, &"h1l".parse().unwrap(), false).unwrap();
+        assert_eq!((section.rows(), section.columns()), (3, 3));
+        for row in 0..3 {
+            for column in 0..3 {
+                assert_eq!(section.value(row, column), (9 * row + 6 + column) as f64);
+            }
+        }
+
+        let outside = "hk5".parse().unwrap();
+        assert!(Section::new(&volume, &outside, false).is_err());
+    }
+}
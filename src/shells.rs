@@ -0,0 +1,79 @@
+use crate::Index;
+
+/// The largest number of neighbour shells of a hamiltonian,
+/// which allows the local neighbour sums to live on the stack
+pub const MAX_SHELLS: usize = 5;
+
+/// A shell of equivalent neighbours on the cyanometalate sublattice.
+/// In grid units the cyanometalates form a fcc lattice, so a vector connects two
+/// cyanometalates exactly when the sum of its components is even.
+#[derive(Clone, Debug)]
+pub struct Shell {
+    /// The squared length of the neighbour vectors in grid units
+    squared_radius: isize,
+    /// All vectors from a site to its neighbours in this shell
+    vectors: Vec<Index>,
+}
+
+impl Shell {
+    /// Getter function for the squared length of the neighbour vectors in grid units
+    pub fn squared_radius(&self) -> isize {
+        self.squared_radius
+    }
+
+    /// Getter function for the neighbour vectors
+    pub fn vectors(&self) -> &[Index] {
+        &self.vectors
+    }
+
+    /// The number of neighbours of a site in this shell
+    pub fn coordination(&self) -> usize {
+        self.vectors.len()
+    }
+
+    /// Whether all vectors fit into half of a grid with the given dims,
+    /// otherwise a vector and the one wrapped around the grid can be the same neighbour
+    pub fn fits_into(&self, dims: (usize, usize, usize)) -> bool {
+        let (a, b, c) = dims;
+        self.vectors.iter().all(|(i, j, k)| {
+            2 * i.unsigned_abs() <= a && 2 * j.unsigned_abs() <= b && 2 * k.unsigned_abs() <= c
+        })
+    }
+}
+
+/// Generates the first count neighbour shells of the cyanometalate sublattice.
+/// The shells are ordered by distance, the first one are the 12 nearest neighbours
+/// along the face diagonals and the second one the 6 neighbours along the axes.
+/// Note that for the shells to be meaningful all vectors should fit into half the grid,
+/// which is checked when a model is built.
+pub fn neighbour_shells(count: usize) -> Vec<Shell> {
+    let mut shells = Vec::with_capacity(count);
+    let mut squared_radius = 0;
+    while shells.len() < count {
+        squared_radius += 2;
+        let vectors = vectors_with_length(squared_radius);
+        if !vectors.is_empty() {
+            shells.push(Shell {
+                squared_radius,
+                vectors,
+            })
+        }
+    }
+    shells
+}
+
+/// All vectors on the sublattice with the given squared length
+fn vectors_with_length(squared_radius: isize) -> Vec<Index> {
+    let max = (squared_radius as f64).sqrt().ceil() as isize;
+    let mut vectors = Vec::new();
+    for i in -max..=max {
+        for j in -max..=max {
+            for k in -max..=max {
+                if (i + j + k) % 2 == 0 && i * i + j * j + k * k == squared_radius {
+                    vectors.push((i, j, k))
+                }
+            }
+        }
+    }
+    vectors
+}
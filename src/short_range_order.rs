@@ -0,0 +1,177 @@
+use std::io::Write;
+use std::path::Path;
+
+use crate::{cyanometalate_sites, Index, Model, StreamingStats};
+
+/// The vectors of the cyanometalate sublattice that are equivalent to lmn under the cubic symmetry
+#[derive(Clone, Debug)]
+struct VectorClass {
+    /// The representative with l >= m >= n >= 0 in grid units
+    lmn: Index,
+    vectors: Vec<Index>,
+}
+
+/// The Warren-Cowley short range order parameters of the cyanometalates and vacancies.
+/// With sigma = 1 for a cyanometalate and -1 for a vacancy and the mean m of sigma,
+/// alpha_lmn = (<sigma_0 sigma_lmn> - m^2) / (1 - m^2),
+/// which is 0 for a random distribution, positive if equal neighbours are preferred
+/// and negative if unlike neighbours are preferred.
+/// A snapshot without vacancies or without cyanometalates has no disorder,
+/// alpha is defined as 0 for it.
+/// The parameters are averaged over the symmetry equivalent vectors and over the snapshots.
+#[derive(Clone, Debug)]
+pub struct ShortRangeOrder {
+    classes: Vec<VectorClass>,
+    /// The alpha of every class in every snapshot
+    alphas: Vec<StreamingStats>,
+}
+
+impl ShortRangeOrder {
+    /// Creates the parameters of all vectors up to max_radius given in unit cells,
+    /// ordered by their length
+    pub fn new(max_radius: f32) -> Self {
+        // a unit cell is two grid points long
+        let max = (2.0 * max_radius).floor() as isize;
+        let mut classes: Vec<VectorClass> = Vec::new();
+        for i in -max..=max {
+            for j in -max..=max {
+                for k in -max..=max {
+                    let squared_radius = i * i + j * j + k * k;
+                    if (i + j + k) % 2 != 0 || squared_radius > max * max || squared_radius == 0 {
+                        continue;
+                    }
+                    let mut lmn = [i.abs(), j.abs(), k.abs()];
+                    lmn.sort_by(|a, b| b.cmp(a));
+                    let lmn = (lmn[0], lmn[1], lmn[2]);
+                    match classes.iter_mut().find(|class| class.lmn == lmn) {
+                        Some(class) => class.vectors.push((i, j, k)),
+                        None => classes.push(VectorClass {
+                            lmn,
+                            vectors: vec![(i, j, k)],
+                        }),
+                    }
+                }
+            }
+        }
+        classes.sort_by_key(|class| {
+            let (l, m, n) = class.lmn;
+            (l * l + m * m + n * n, std::cmp::Reverse(class.lmn))
+        });
+        Self {
+            alphas: vec![StreamingStats::new(); classes.len()],
+            classes,
+        }
+    }
+
+    /// Adds the parameters of the current configuration of the model
+    pub fn add(&mut self, model: &Model) {
+        if model.metalates() == 0 || model.metalates() == model.sites() {
+            self.alphas
+                .iter_mut()
+                .for_each(|alpha| alpha.add_value(0.0));
+            return;
+        }
+        let grid = &model.grid;
+        let m = 2.0 * model.fill_frac() - 1.0;
+        for (class, alpha) in self.classes.iter().zip(self.alphas.iter_mut()) {
+            let mut sum = 0;
+            for (i, j, k) in cyanometalate_sites(model.dims()) {
+                let sigma = grid[(i, j, k)] as i64;
+                for (di, dj, dk) in &class.vectors {
+                    sum += sigma * grid[(i + di, j + dj, k + dk)] as i64;
+                }
+            }
+            let correlation = sum as f64 / (model.sites() * class.vectors.len()) as f64;
+            alpha.add_value((correlation - m * m) / (1.0 - m * m));
+        }
+    }
+
+    /// The number of snapshots
+    pub fn snapshots(&self) -> u64 {
+        self.alphas.first().map_or(0, |alpha| alpha.count())
+    }
+
+    /// The vectors lmn in unit cells, their multiplicity and the mean of alpha over the snapshots
+    pub fn alphas(&self) -> Vec<((f32, f32, f32), usize, f64)> {
+        self.classes
+            .iter()
+            .zip(&self.alphas)
+            .map(|(class, alpha)| {
+                let (l, m, n) = class.lmn;
+                (
+                    (l as f32 / 2.0, m as f32 / 2.0, n as f32 / 2.0),
+                    class.vectors.len(),
+                    alpha.avg(),
+                )
+            })
+            .collect()
+    }
+
+    /// Writes the parameters to a csv file, lmn are given in unit cells
+    /// and the standard deviation is the one of the snapshots
+    pub fn write_to_csv(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        writeln!(file, "{} snapshots", self.snapshots())?;
+        writeln!(file, "l,m,n,multiplicity,alpha,alpha_std")?;
+        for (((l, m, n), multiplicity, alpha), stats) in self.alphas().into_iter().zip(&self.alphas)
+        {
+            writeln!(
+                file,
+                "{},{},{},{},{},{}",
+                l,
+                m,
+                n,
+                multiplicity,
+                alpha,
+                stats.variance().sqrt()
+            )?;
+        }
+        file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classes_up_to_a_unit_cell() {
+        let sro = ShortRangeOrder::new(1.0);
+        let classes: Vec<_> = sro
+            .alphas()
+            .into_iter()
+            .map(|(lmn, multiplicity, _)| (lmn, multiplicity))
+            .collect();
+        assert_eq!(classes, [((0.5, 0.5, 0.0), 12), ((1.0, 0.0, 0.0), 6)]);
+    }
+
+    #[test]
+    fn alternating_planes() {
+        // the planes of constant i are alternately occupied and empty
+        let mut model = Model::new((4, 4, 4), vec![0.0, 0.0], 0.5, None).unwrap();
+        for (i, j, k) in cyanometalate_sites(model.dims()) {
+            model.grid[(i, j, k)] = if i % 2 == 0 { 1 } else { -1 };
+        }
+        assert_eq!(model.metalates(), model.sites() / 2);
+        let mut sro = ShortRangeOrder::new(1.0);
+        sro.add(&model);
+        sro.add(&model);
+        assert_eq!(sro.snapshots(), 2);
+        // 8 of the 12 nearest neighbours lie in the neighbouring planes
+        let alphas: Vec<f64> = sro
+            .alphas()
+            .into_iter()
+            .map(|(_, _, alpha)| alpha)
+            .collect();
+        assert!((alphas[0] + 1.0 / 3.0).abs() < 1e-12, "{}", alphas[0]);
+        assert!((alphas[1] - 1.0).abs() < 1e-12, "{}", alphas[1]);
+    }
+
+    #[test]
+    fn no_disorder_without_vacancies() {
+        let model = Model::new((4, 4, 4), vec![0.0, 0.0], 1.0, None).unwrap();
+        let mut sro = ShortRangeOrder::new(1.0);
+        sro.add(&model);
+        assert!(sro.alphas().iter().all(|(_, _, alpha)| *alpha == 0.0));
+    }
+}
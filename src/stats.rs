@@ -1,37 +1,444 @@
-// TODO check implemetation and source mentioned in stackexchange
+/// The minimal number of blocks of a binning level for its error estimate to be used
+const MIN_BLOCKS: u64 = 32;
+/// The number of bins of the jackknife is kept between this and twice this value
+const JACKKNIFE_BINS: usize = 32;
+
+/// Mean and central moments of a stream of values up to the fourth,
+/// updated with the one pass formulas of Pébay (2008) in f64,
+/// so long streams of single precision energies keep their precision.
+/// Additionally, a binning analysis estimates the error of the mean
+/// and the integrated autocorrelation time of correlated values,
+/// and the values are collected in bins for jackknife estimates of derived quantities.
+/// Accumulators of independent streams, e.g. of several chains, can be combined with merge.
+#[derive(Clone, Debug)]
 pub struct StreamingStats {
-    count: u32,
-    m_k: f32,
-    m_k_1: f32,
-    v_k: f32,
-    v_k_1: f32,
+    count: u64,
+    mean: f64,
+    /// The sums of the 2nd, 3rd and 4th powers of the deviations from the mean
+    m_2: f64,
+    m_3: f64,
+    m_4: f64,
+    /// The binning levels, level k contains the means of blocks of 2^k values
+    levels: Vec<BinningLevel>,
+    /// The bins of the jackknife, they contain at most bin_size values
+    bins: Vec<Bin>,
+    bin_size: u64,
+}
+
+/// The block means of a single level of the binning analysis
+#[derive(Clone, Copy, Debug, Default)]
+struct BinningLevel {
+    count: u64,
+    sum: f64,
+    sum_sq: f64,
+    /// The first value of the block that is waiting for its partner
+    pending: Option<f64>,
+}
+
+/// The sums of the values in a jackknife bin
+#[derive(Clone, Copy, Debug, Default)]
+struct Bin {
+    count: u64,
+    sum: f64,
+    sum_sq: f64,
 }
 
 impl StreamingStats {
-    // variance after https://math.stackexchange.com/questions/20593/calculate-variance-from-a-stream-of-sample-values
     pub fn new() -> Self {
         Self {
             count: 0,
-            m_k: 0.0,
-            m_k_1: 0.0,
-            v_k: 0.0,
-            v_k_1: 0.0,
+            mean: 0.0,
+            m_2: 0.0,
+            m_3: 0.0,
+            m_4: 0.0,
+            levels: Vec::new(),
+            bins: Vec::new(),
+            bin_size: 1,
         }
     }
 
-    pub fn add_value(&mut self, x_k: f32) {
+    pub fn add_value(&mut self, x: f64) {
+        let n_1 = self.count as f64;
         self.count += 1;
-        self.m_k_1 = self.m_k;
-        self.v_k_1 = self.v_k;
-        self.m_k = self.m_k_1 + (x_k - self.m_k_1) / self.count as f32;
-        self.v_k = self.v_k_1 + (x_k - self.m_k_1) * (x_k - self.m_k);
+        let n = self.count as f64;
+        let delta = x - self.mean;
+        let delta_n = delta / n;
+        let delta_n_sq = delta_n * delta_n;
+        let term = delta * delta_n * n_1;
+        self.mean += delta_n;
+        self.m_4 += term * delta_n_sq * (n * n - 3.0 * n + 3.0) + 6.0 * delta_n_sq * self.m_2
+            - 4.0 * delta_n * self.m_3;
+        self.m_3 += term * delta_n * (n - 2.0) - 3.0 * delta_n * self.m_2;
+        self.m_2 += term;
+        self.add_to_levels(0, x);
+        self.add_to_bins(x);
+    }
+
+    /// Combines the statistics of another stream with these, as if its values had been added.
+    /// The moments are exact, the blocks of the binning analysis and the jackknife bins
+    /// at the border of the streams contain values of both
+    pub fn merge(&mut self, other: &StreamingStats) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            *self = other.clone();
+            return;
+        }
+        let (n_a, n_b) = (self.count as f64, other.count as f64);
+        let n = n_a + n_b;
+        let delta = other.mean - self.mean;
+        let delta_sq = delta * delta;
+        self.m_4 += other.m_4
+            + delta_sq * delta_sq * n_a * n_b * (n_a * n_a - n_a * n_b + n_b * n_b) / (n * n * n)
+            + 6.0 * delta_sq * (n_a * n_a * other.m_2 + n_b * n_b * self.m_2) / (n * n)
+            + 4.0 * delta * (n_a * other.m_3 - n_b * self.m_3) / n;
+        self.m_3 += other.m_3
+            + delta_sq * delta * n_a * n_b * (n_a - n_b) / (n * n)
+            + 3.0 * delta * (n_a * other.m_2 - n_b * self.m_2) / n;
+        self.m_2 += other.m_2 + delta_sq * n_a * n_b / n;
+        self.mean += delta * n_b / n;
+        self.count += other.count;
+
+        for (k, level) in other.levels.iter().enumerate() {
+            if k == self.levels.len() {
+                self.levels.push(BinningLevel::default());
+            }
+            let own = &mut self.levels[k];
+            own.count += level.count;
+            own.sum += level.sum;
+            own.sum_sq += level.sum_sq;
+            if let Some(value) = level.pending {
+                match own.pending.take() {
+                    Some(first) => self.add_to_levels(k + 1, (first + value) / 2.0),
+                    None => own.pending = Some(value),
+                }
+            }
+        }
+
+        self.bins.extend_from_slice(&other.bins);
+        self.bin_size = self.bin_size.max(other.bin_size);
+        while self.bins.len() > 2 * JACKKNIFE_BINS {
+            self.merge_bins();
+        }
+    }
+
+    /// Adds the value to the given level, completed blocks are passed up to the next level
+    fn add_to_levels(&mut self, level: usize, x: f64) {
+        let mut value = x;
+        for k in level.. {
+            if k == self.levels.len() {
+                self.levels.push(BinningLevel::default());
+            }
+            let level = &mut self.levels[k];
+            level.count += 1;
+            level.sum += value;
+            level.sum_sq += value * value;
+            match level.pending.take() {
+                Some(first) => value = (first + value) / 2.0,
+                None => {
+                    level.pending = Some(value);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Merges neighbouring jackknife bins, which doubles the bin size
+    fn merge_bins(&mut self) {
+        self.bins = self
+            .bins
+            .chunks(2)
+            .map(|pair| Bin {
+                count: pair.iter().map(|bin| bin.count).sum(),
+                sum: pair.iter().map(|bin| bin.sum).sum(),
+                sum_sq: pair.iter().map(|bin| bin.sum_sq).sum(),
+            })
+            .collect();
+        self.bin_size *= 2;
+    }
+
+    /// Adds the value to the last bin, if there are too many bins neighbouring bins are merged
+    fn add_to_bins(&mut self, x: f64) {
+        let is_full = |bins: &[Bin], bin_size| bins.last().is_none_or(|bin| bin.count >= bin_size);
+        if is_full(&self.bins, self.bin_size) {
+            if self.bins.len() >= 2 * JACKKNIFE_BINS {
+                self.merge_bins();
+            }
+            if is_full(&self.bins, self.bin_size) {
+                self.bins.push(Bin::default());
+            }
+        }
+        let bin = self.bins.last_mut().expect("a bin was pushed above");
+        bin.count += 1;
+        bin.sum += x;
+        bin.sum_sq += x * x;
+    }
+
+    pub fn avg(&self) -> f64 {
+        self.mean
+    }
+
+    pub fn variance(&self) -> f64 {
+        self.m_2 / self.count as f64
+    }
+
+    /// The standardised third central moment, 0 for symmetric distributions
+    pub fn skewness(&self) -> f64 {
+        (self.count as f64).sqrt() * self.m_3 / self.m_2.powf(1.5)
+    }
+
+    /// The standardised fourth central moment, 3 for a normal distribution
+    pub fn kurtosis(&self) -> f64 {
+        self.count as f64 * self.m_4 / (self.m_2 * self.m_2)
+    }
+
+    /// The fourth order cumulant `1 - <x^4> / (3 <x^2>^2)` of the raw moments.
+    /// For the energy it approaches 2/3 away from first order transitions
+    pub fn binder_cumulant(&self) -> f64 {
+        let n = self.count as f64;
+        let mean_sq = self.mean * self.mean;
+        let moment_2 = self.m_2 / n + mean_sq;
+        let moment_4 = self.m_4 / n
+            + 4.0 * self.mean * self.m_3 / n
+            + 6.0 * mean_sq * self.m_2 / n
+            + mean_sq * mean_sq;
+        1.0 - moment_4 / (3.0 * moment_2 * moment_2)
+    }
+
+    /// The number of values
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The standard error of the mean from the blocks of every binning level,
+    /// starting with the naive error of uncorrelated values.
+    /// The estimates grow with the level until the blocks are longer than the autocorrelation time
+    pub fn binning_errors(&self) -> Vec<f64> {
+        self.levels
+            .iter()
+            .filter(|level| level.count > 1)
+            .map(|level| {
+                let n = level.count as f64;
+                let mean = level.sum / n;
+                let variance = (level.sum_sq / n - mean * mean).max(0.0);
+                (variance / (n - 1.0)).sqrt()
+            })
+            .collect()
+    }
+
+    /// The standard error of the mean of correlated values.
+    /// It is the estimate of the highest binning level that still has enough blocks.
+    /// NaN if the binning did not converge, that is if no level above the unbinned values
+    /// has at least MIN_BLOCKS blocks, as the naive error underestimates correlated errors
+    pub fn error(&self) -> f64 {
+        let errors = self.binning_errors();
+        let usable = self
+            .levels
+            .iter()
+            .take(errors.len())
+            .filter(|level| level.count >= MIN_BLOCKS)
+            .count();
+        match usable {
+            0 | 1 => f64::NAN,
+            usable => errors[usable - 1],
+        }
+    }
+
+    /// The integrated autocorrelation time in units of the spacing of the values,
+    /// from the ratio of the binning error and the naive error.
+    /// It is 0.5 for uncorrelated values and NaN if the binning did not converge
+    pub fn autocorrelation_time(&self) -> f64 {
+        let naive = self.binning_errors().first().copied().unwrap_or(f64::NAN);
+        let error = self.error();
+        if error.is_nan() {
+            return f64::NAN;
+        }
+        if naive == 0.0 {
+            return 0.5;
+        }
+        0.5 * (error / naive).powi(2)
+    }
+
+    /// Jackknife estimate and error of a quantity derived from the mean and the mean of the squares,
+    /// e.g. the heat capacity `|mean, mean_sq| (mean_sq - mean * mean) / T^2`.
+    /// NaN if there are less than 2 bins
+    pub fn jackknife(&self, derived: impl Fn(f64, f64) -> f64) -> (f64, f64) {
+        let bins = self.bins.len();
+        if bins < 2 {
+            return (f64::NAN, f64::NAN);
+        }
+        let count: u64 = self.bins.iter().map(|bin| bin.count).sum();
+        let sum: f64 = self.bins.iter().map(|bin| bin.sum).sum();
+        let sum_sq: f64 = self.bins.iter().map(|bin| bin.sum_sq).sum();
+        let full = derived(sum / count as f64, sum_sq / count as f64);
+
+        let leave_one_out: Vec<f64> = self
+            .bins
+            .iter()
+            .map(|bin| {
+                let n = (count - bin.count) as f64;
+                derived((sum - bin.sum) / n, (sum_sq - bin.sum_sq) / n)
+            })
+            .collect();
+        let mean = leave_one_out.iter().sum::<f64>() / bins as f64;
+        let variance = leave_one_out
+            .iter()
+            .map(|value| (value - mean).powi(2))
+            .sum::<f64>()
+            * (bins - 1) as f64
+            / bins as f64;
+        // bias corrected estimate
+        let estimate = bins as f64 * full - (bins - 1) as f64 * mean;
+        (estimate, variance.sqrt())
+    }
+}
+
+impl Default for StreamingStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha12Rng;
+
+    fn stats_of(values: &[f64]) -> StreamingStats {
+        let mut stats = StreamingStats::new();
+        values.iter().for_each(|x| stats.add_value(*x));
+        stats
+    }
+
+    /// The central moment of the given order with two passes over the values
+    fn central_moment(values: &[f64], order: i32) -> f64 {
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        values.iter().map(|x| (x - mean).powi(order)).sum::<f64>() / values.len() as f64
+    }
+
+    #[test]
+    fn known_moments() {
+        let stats = stats_of(&[1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(stats.count(), 4);
+        assert_eq!(stats.avg(), 2.5);
+        assert_eq!(stats.variance(), 1.25);
+        assert_eq!(stats.skewness(), 0.0);
+        assert!((stats.kurtosis() - 1.64).abs() < 1e-12);
+    }
+
+    #[test]
+    fn merge_gives_the_moments_of_all_values() {
+        let a = [1.0, 2.0, 3.0, 4.0, 10.0];
+        let b = [-2.0, 0.0, 7.0, 7.5, -1.0, 3.0, 12.0];
+        let all: Vec<f64> = a.iter().chain(&b).copied().collect();
+        let mut merged = stats_of(&a);
+        merged.merge(&stats_of(&b));
+
+        let variance = central_moment(&all, 2);
+        assert_eq!(merged.count(), 12);
+        assert!((merged.avg() - all.iter().sum::<f64>() / 12.0).abs() < 1e-12);
+        assert!((merged.variance() - variance).abs() < 1e-12);
+        let skewness = central_moment(&all, 3) / variance.powf(1.5);
+        assert!((merged.skewness() - skewness).abs() < 1e-12);
+        let kurtosis = central_moment(&all, 4) / (variance * variance);
+        assert!((merged.kurtosis() - kurtosis).abs() < 1e-12);
+
+        let sequential = stats_of(&all);
+        assert!((merged.binder_cumulant() - sequential.binder_cumulant()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn binning_of_an_ar1_process() {
+        // x_t = phi x_{t-1} + noise has tau_int = (1 + phi) / (2 (1 - phi))
+        let phi: f64 = 0.8;
+        let n = 1 << 20;
+        let mut rng = ChaCha12Rng::seed_from_u64(16);
+        let mut x = 0.0;
+        let values: Vec<f64> = (0..n)
+            .map(|_| {
+                x = phi * x + rng.gen_range(-1.0..1.0);
+                x
+            })
+            .collect();
+        let stats = stats_of(&values);
+
+        // the variance of the uniform noise is 1/3
+        let variance = 1.0 / 3.0 / (1.0 - phi * phi);
+        assert!((stats.variance() / variance - 1.0).abs() < 0.02);
+        let tau = (1.0 + phi) / (2.0 * (1.0 - phi));
+        let error = (2.0 * tau * variance / n as f64).sqrt();
+        // blocks of 256 values are much longer than tau and there are 4096 of them
+        let binned = stats.binning_errors()[8];
+        assert!(
+            (binned / error - 1.0).abs() < 0.05,
+            "{} != {}",
+            binned,
+            error
+        );
+        // the naive error underestimates the error by sqrt(2 tau)
+        let naive = stats.binning_errors()[0];
+        assert!((naive / error * (2.0 * tau).sqrt() - 1.0).abs() < 0.05);
+        // the highest level only has 32 blocks, which gives 13 % statistical error
+        // of the error and twice that of tau
+        assert!((stats.error() / error - 1.0).abs() < 0.25);
+        assert!(
+            (stats.autocorrelation_time() / tau - 1.0).abs() < 0.4,
+            "{} != {}",
+            stats.autocorrelation_time(),
+            tau
+        );
+    }
+
+    #[test]
+    fn jackknife_of_the_mean_and_variance() {
+        let values: Vec<f64> = (0..128).map(|i| ((i * 37) % 101) as f64 / 7.0).collect();
+        let mean = values.iter().sum::<f64>() / 128.0;
+
+        // 128 values are collected in 64 bins of 2,
+        // the jackknife error of the mean is the standard error of the bin means
+        let stats = stats_of(&values);
+        let bin_means: Vec<f64> = values
+            .chunks(2)
+            .map(|pair| (pair[0] + pair[1]) / 2.0)
+            .collect();
+        let error = (central_moment(&bin_means, 2) / (bin_means.len() - 1) as f64).sqrt();
+        let (estimate, jackknife_error) = stats.jackknife(|mean, _| mean);
+        assert!((estimate - mean).abs() < 1e-12);
+        assert!((jackknife_error - error).abs() < 1e-12);
+
+        // with bins of single values the bias corrected variance is the unbiased variance
+        let stats = stats_of(&values[..64]);
+        let unbiased = central_moment(&values[..64], 2) * 64.0 / 63.0;
+        let (estimate, _) = stats.jackknife(|mean, mean_sq| mean_sq - mean * mean);
+        assert!(
+            (estimate - unbiased).abs() < 1e-9,
+            "{} != {}",
+            estimate,
+            unbiased
+        );
     }
 
-    pub fn avg(&self) -> f32 {
-        self.m_k
+    #[test]
+    fn known_binder_cumulants() {
+        // 1 - <x^4> / (3 <x^2>^2)
+        assert!((stats_of(&[-1.0, 1.0, 1.0, -1.0]).binder_cumulant() - 2.0 / 3.0).abs() < 1e-12);
+        assert!((stats_of(&[-3.5; 5]).binder_cumulant() - 2.0 / 3.0).abs() < 1e-12);
+        assert!((stats_of(&[0.0, 0.0, 0.0, 2.0]).binder_cumulant() + 1.0 / 3.0).abs() < 1e-12);
+        // <x^2> = 5, <x^4> = 41
+        let binder = 1.0 - 41.0 / 75.0;
+        assert!((stats_of(&[1.0, -3.0, 3.0, -1.0]).binder_cumulant() - binder).abs() < 1e-12);
     }
 
-    pub fn variance(&self) -> f32 {
-        self.v_k / self.count as f32
+    #[test]
+    fn merge_with_empty_stats() {
+        let values = [1.0, 5.0, 2.0];
+        let mut merged = StreamingStats::new();
+        merged.merge(&stats_of(&values));
+        merged.merge(&StreamingStats::new());
+        assert_eq!(merged.count(), 3);
+        assert_eq!(merged.avg(), stats_of(&values).avg());
+        assert_eq!(merged.variance(), stats_of(&values).variance());
     }
 }
@@ -0,0 +1,924 @@
+use rayon::prelude::*;
+use std::error::Error;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::{
+    Checkpoint, CsvLogger, DiffuseScattering, EquilibrationDetector, Failure, Manifest, Model,
+    ModelError, NeighbourHistogram, OrderParameter, PairHamiltonian, ParallelTempering, Plane,
+    Point, Section, ShortRangeOrder, StreamingStats, SweepConfig, TemperingCheckpoint, Trajectory,
+};
+
+const COLUMNS: [&str; 20] = [
+    "j_prime",
+    "temp",
+    "energy",
+    "variance",
+    "fill_frac",
+    "swap_acceptance",
+    "j_2",
+    "initial_fill_frac",
+    "supercells_a",
+    "supercells_b",
+    "supercells_c",
+    "eq_sweeps",
+    "energy_err",
+    "heat_capacity",
+    "heat_capacity_err",
+    "tau_int",
+    "binder_cumulant",
+    "j_3",
+    "j_4",
+    "j_5",
+];
+
+/// The number of equilibration sweeps between two checks for stationarity
+const CHECK_INTERVAL: usize = 10;
+
+/// The optional outputs of a point that are collected while it is simulated
+#[derive(Default)]
+struct Recorders {
+    trajectory: Option<Trajectory>,
+    histogram: Option<NeighbourHistogram>,
+    short_range_order: Option<ShortRangeOrder>,
+    /// The average of the snapshots of the diffuse scattering
+    scattering: Option<DiffuseScattering>,
+    /// The number of sampled sweeps
+    samples: usize,
+}
+
+/// Runs the chains of a sweep and keeps track of its progress in the manifest of the run.
+/// Completed points are recorded as soon as their row is written and their model is saved,
+/// so a run that was interrupted can be resumed and only the missing points are computed again.
+/// Failing points are reported instead of aborting the whole sweep.
+pub struct SweepRunner {
+    config: SweepConfig,
+    name: String,
+    manifest: Mutex<Manifest>,
+    /// The observables that are recorded in the trajectories in addition to the energy
+    order_parameters: Vec<OrderParameter>,
+}
+
+impl SweepRunner {
+    /// Creates the directories of a new run named after the current time
+    /// and writes its config and manifest
+    pub fn new(mut config: SweepConfig) -> Result<Self, Box<dyn Error>> {
+        config.validate()?;
+        // without a seed the run could not be reproduced from its manifest
+        config
+            .seed
+            .get_or_insert_with(|| format!("{:016x}", rand::random::<u64>()));
+        let out = Path::new(&config.output);
+        std::fs::create_dir_all(out.join("csv"))?;
+        std::fs::create_dir_all(out.join("mmcif"))?;
+        std::fs::create_dir_all(out.join("models"))?;
+        // creating the directory claims the name, runs started at the same time get a suffix
+        let time = chrono::Utc::now().format("%Y-%m-%d_%H-%M-%S").to_string();
+        let mut name = time.clone();
+        let mut suffix = 1;
+        loop {
+            match std::fs::create_dir(out.join("models").join(&name)) {
+                Ok(()) => break,
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                    name = format!("{}_{}", time, suffix);
+                    suffix += 1;
+                }
+                Err(err) => {
+                    return Err(format!("could not create the run {}: {}", name, err).into())
+                }
+            }
+        }
+        std::fs::create_dir_all(out.join("mmcif").join(&name))?;
+        if config.trajectories {
+            std::fs::create_dir_all(out.join("trajectories").join(&name))?;
+        }
+        if config.histograms {
+            std::fs::create_dir_all(out.join("histograms").join(&name))?;
+        }
+        if config.sro_radius.is_some() {
+            std::fs::create_dir_all(out.join("sro").join(&name))?;
+        }
+        if config.scattering_max_hkl.is_some() {
+            std::fs::create_dir_all(out.join("h5").join(&name))?;
+        }
+        if config.section.is_some() {
+            std::fs::create_dir_all(out.join("sections").join(&name))?;
+            std::fs::create_dir_all(out.join("maps").join(&name))?;
+        }
+        config.write_toml(out.join("models").join(&name).join("config.toml"))?;
+
+        let mut manifest = Manifest::new(&name, &config);
+        manifest.files = vec![
+            format!("models/{}/config.toml", name),
+            format!("csv/{}.csv", name),
+        ];
+        let runner = Self {
+            config,
+            name,
+            manifest: Mutex::new(manifest),
+            order_parameters: Vec::new(),
+        };
+        runner.update(|_| ())?;
+        Ok(runner)
+    }
+
+    /// Opens an existing run in the output directory to compute its missing points
+    pub fn resume(output: impl AsRef<Path>, run: &str) -> Result<Self, Box<dyn Error>> {
+        let out = output.as_ref();
+        let mut manifest = Manifest::from_toml(out.join("models").join(run).join("manifest.toml"))
+            .map_err(|err| format!("could not read the manifest of {}: {}", run, err))?;
+        manifest.parameters.output = out.to_string_lossy().into_owned();
+        manifest.end_time = None;
+        manifest.failed.clear();
+        std::fs::create_dir_all(out.join("mmcif").join(run))?;
+        if manifest.parameters.trajectories {
+            std::fs::create_dir_all(out.join("trajectories").join(run))?;
+        }
+        if manifest.parameters.histograms {
+            std::fs::create_dir_all(out.join("histograms").join(run))?;
+        }
+        if manifest.parameters.sro_radius.is_some() {
+            std::fs::create_dir_all(out.join("sro").join(run))?;
+        }
+        if manifest.parameters.scattering_max_hkl.is_some() {
+            std::fs::create_dir_all(out.join("h5").join(run))?;
+        }
+        if manifest.parameters.section.is_some() {
+            std::fs::create_dir_all(out.join("sections").join(run))?;
+            std::fs::create_dir_all(out.join("maps").join(run))?;
+        }
+
+        // the rows are written asynchronously, so a point can be recorded as completed
+        // without its row if the run was interrupted in between, such points are computed again
+        let csv = out.join("csv").join(format!("{}.csv", run));
+        let rows = if csv.exists() {
+            prune_csv(&csv, &manifest.completed)?
+        } else {
+            Vec::new()
+        };
+        manifest.completed.retain(|point| rows.contains(point));
+        let runner = Self {
+            config: manifest.parameters.clone(),
+            name: run.to_string(),
+            manifest: Mutex::new(manifest),
+            order_parameters: Vec::new(),
+        };
+        runner.update(|_| ())?;
+        Ok(runner)
+    }
+
+    /// The name of the run
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Adds an observable that is recorded in every row of the trajectories
+    pub fn add_order_parameter(
+        &mut self,
+        name: impl Into<String>,
+        measure: impl Fn(&Model) -> f64 + Send + Sync + 'static,
+    ) {
+        self.order_parameters.push(OrderParameter {
+            name: name.into(),
+            measure: Box::new(measure),
+        })
+    }
+
+    /// Runs all chains that have missing points in parallel and returns the failures.
+    /// The most expensive chains are started first and every chain is a separate task,
+    /// so the rayon workers stay busy until the end of the sweep
+    pub fn run(&self) -> Result<Vec<Failure>, Box<dyn Error>> {
+        let out = Path::new(&self.config.output);
+        let csv = out.join("csv").join(format!("{}.csv", self.name));
+        let (logger, handle) = if csv.exists() {
+            CsvLogger::append(csv.to_string_lossy().into_owned(), COLUMNS.len())
+        } else {
+            CsvLogger::new(
+                csv.to_string_lossy().into_owned(),
+                self.csv_header(),
+                COLUMNS.map(String::from).to_vec(),
+            )
+        };
+
+        let mut chains = self.config.chains();
+        chains.retain(|chain| chain.iter().any(|point| !self.is_completed(point)));
+        chains.sort_by_key(|chain| std::cmp::Reverse(chain.len() * chain[0].sites()));
+
+        chains
+            .par_iter()
+            .with_max_len(1)
+            .for_each_with(logger, |logger, chain| {
+                let result = catch_unwind(AssertUnwindSafe(|| {
+                    if self.config.tempering {
+                        self.run_tempering(chain, logger)
+                    } else {
+                        self.run_chain(chain, logger)
+                    }
+                }));
+                if let Err(panic) = result {
+                    let error = panic
+                        .downcast_ref::<&str>()
+                        .map(|msg| msg.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .unwrap_or_else(|| "panicked".to_string());
+                    self.fail(self.config.chain_name(&chain[0]), error);
+                }
+            });
+        handle
+            .join()
+            .map_err(|_| "the csv logger panicked")?
+            .map_err(|err| format!("could not write the csv file: {}", err))?;
+
+        if let Some(plane) = &self.config.section {
+            self.write_maps(plane)?;
+        }
+        let failures = self.manifest.lock().unwrap().failed.clone();
+        self.update(|manifest| manifest.finish())?;
+        Ok(failures)
+    }
+
+    /// Combines the sections of all completed points into maps with J' increasing to the right
+    /// and the temperature decreasing downwards like `make_map_from_yells` in python.
+    /// Points that only differ in J' and temperature share a map
+    fn write_maps(&self, plane: &Plane) -> Result<(), Box<dyn Error>> {
+        let out = Path::new(&self.config.output);
+        let completed = self.manifest.lock().unwrap().completed.clone();
+        let mut maps: Vec<(String, Vec<Point>)> = Vec::new();
+        for point in completed {
+            let name = self.config.map_name(&point);
+            match maps.iter_mut().find(|(map, _)| *map == name) {
+                Some((_, points)) => points.push(point),
+                None => maps.push((name, vec![point])),
+            }
+        }
+        let mut files = Vec::new();
+        for (name, points) in maps {
+            let mut j_primes: Vec<f32> = points.iter().map(|point| point.j_prime).collect();
+            j_primes.sort_by(f32::total_cmp);
+            j_primes.dedup();
+            let mut temps: Vec<f32> = points.iter().map(|point| point.temp).collect();
+            temps.sort_by(|a, b| b.total_cmp(a));
+            temps.dedup();
+            let mut tiles = vec![vec![None; j_primes.len()]; temps.len()];
+            for point in &points {
+                let file = out.join(self.point_file("sections", point, "csv"));
+                if !file.exists() {
+                    continue;
+                }
+                let section = Section::from_csv(&file)
+                    .map_err(|err| format!("could not read {}: {}", file.display(), err))?;
+                let row = temps.iter().position(|temp| *temp == point.temp).unwrap();
+                let column = j_primes.iter().position(|j| *j == point.j_prime).unwrap();
+                tiles[row][column] = Some(section);
+            }
+            let file = match name.as_str() {
+                "" => format!("maps/{}/{}.png", self.name, plane),
+                _ => format!("maps/{}/{}_{}.png", self.name, plane, name),
+            };
+            Section::tile(&tiles)
+                .write_png(out.join(&file), &self.config.rendering())
+                .map_err(|err| format!("could not write the map: {}", err))?;
+            files.push(file);
+        }
+        self.update(|manifest| {
+            for file in files {
+                if !manifest.files.contains(&file) {
+                    manifest.files.push(file)
+                }
+            }
+        })
+    }
+
+    /// The header of the csv file, python expects exactly 3 lines before the column names.
+    /// If the sweep contains several sizes or fill fractions they are only given in the columns
+    fn csv_header(&self) -> String {
+        let sizes = self.config.size.values();
+        let supercells = match sizes.as_slice() {
+            [(a, b, c)] => format!("{} {} {}", a / 2, b / 2, c / 2),
+            _ => "varying".to_string(),
+        };
+        let fill_fracs = self.config.fill_frac.values();
+        let fill_frac = match (sizes.as_slice(), fill_fracs.as_slice()) {
+            ([(a, b, c)], [fill_frac]) => {
+                let sites = a * b * c / 2;
+                format!(
+                    "{}",
+                    (fill_frac * sites as f32).floor() as usize as f32 / sites as f32
+                )
+            }
+            _ => "varying".to_string(),
+        };
+        format!(
+            "energy, variance and heat capacity are given per cyanometalate site\n{} supercells along a, b and c\n{} fill fraction",
+            supercells, fill_frac
+        )
+    }
+
+    /// The models directory of the run
+    fn model_dir(&self) -> PathBuf {
+        Path::new(&self.config.output)
+            .join("models")
+            .join(&self.name)
+    }
+
+    /// Changes the manifest and writes it
+    fn update(&self, change: impl FnOnce(&mut Manifest)) -> Result<(), Box<dyn Error>> {
+        let mut manifest = self.manifest.lock().unwrap();
+        change(&mut manifest);
+        manifest.write_toml(self.model_dir().join("manifest.toml"))
+    }
+
+    fn is_completed(&self, point: &Point) -> bool {
+        self.manifest.lock().unwrap().is_completed(point)
+    }
+
+    /// Reports and records a failure
+    fn fail(&self, name: String, error: String) {
+        let failure = Failure { name, error };
+        eprintln!("{}", failure);
+        if let Err(err) = self.update(|manifest| manifest.failed.push(failure)) {
+            eprintln!("could not write the manifest: {}", err)
+        }
+    }
+
+    /// Takes a single model through all points of the chain.
+    /// The chain continues from its checkpoint if there is one, points that are already
+    /// completed are simulated to advance the chain but not written again.
+    /// The checkpoint is only advanced while all points succeed, so failed points are retried on resume.
+    /// If a point before the checkpoint is missing, e.g. because its row was lost,
+    /// the checkpoint is discarded and the chain starts again from its first point
+    fn run_chain(&self, chain: &[Point], logger: &CsvLogger) {
+        let config = &self.config;
+        let first = chain[0];
+        let checkpoint_path = self
+            .model_dir()
+            .join(format!("{}.checkpoint", config.chain_name(&first)));
+        let new_model = || {
+            Model::new(
+                first.size,
+                first.couplings(),
+                first.fill_frac,
+                config.chain_seed(&first).as_deref(),
+            )
+            .map(|model| (model, 0))
+            .map_err(|err| err.to_string())
+        };
+        let loaded: Result<_, String> = match Checkpoint::load(&checkpoint_path) {
+            Ok(checkpoint)
+                if chain
+                    .iter()
+                    .take(checkpoint.schedule_pos)
+                    .all(|point| self.is_completed(point)) =>
+            {
+                Ok((checkpoint.model, checkpoint.schedule_pos))
+            }
+            Ok(_) => new_model(),
+            Err(ModelError::Io(err)) if err.kind() == std::io::ErrorKind::NotFound => new_model(),
+            Err(err) => Err(format!("could not resume from the checkpoint: {}", err)),
+        };
+        let (mut model, start) = match loaded {
+            Ok(loaded) => loaded,
+            Err(err) => return self.fail(config.chain_name(&first), err),
+        };
+
+        let mut healthy = true;
+        for (i, point) in chain.iter().enumerate().skip(start) {
+            if model.hamiltonian().couplings() != point.couplings().as_slice() {
+                if let Err(err) = model.set_hamiltonian(PairHamiltonian::new(point.couplings())) {
+                    return self.fail(config.point_name(point), err.to_string());
+                }
+            }
+            let sites = point.sites();
+            let beta = 1.0 / point.temp;
+            let mut recorders = self.recorders(point, &model);
+            let mut detector = EquilibrationDetector::new(config.min_eq_epochs);
+            let mut eq_sweeps = 0;
+            while eq_sweeps < config.eq_epochs {
+                for _ in 0..2 * sites {
+                    self.step(&mut model, beta)
+                }
+                eq_sweeps += 1;
+                self.record(&mut recorders, &model, false);
+                if config.auto_equilibration {
+                    detector.add_value(model.get_hamiltonian() as f64);
+                    if eq_sweeps % CHECK_INTERVAL == 0 && detector.is_equilibrated() {
+                        break;
+                    }
+                }
+            }
+
+            // the values are sampled once per sweep, as consecutive steps are strongly correlated
+            let mut stats = StreamingStats::new();
+            let mut fill_stats = StreamingStats::new();
+            for _ in 0..config.epoch {
+                for _ in 0..2 * sites {
+                    self.step(&mut model, beta);
+                }
+                stats.add_value(model.get_hamiltonian() as f64);
+                fill_stats.add_value(model.fill_frac());
+                self.record(&mut recorders, &model, true);
+            }
+            let row = self.row(point, &stats, &fill_stats, f32::NAN, eq_sweeps);
+            if let Err(err) = self.complete_point(point, &model, row, recorders, logger) {
+                self.fail(config.point_name(point), err.to_string());
+                healthy = false;
+            }
+
+            if healthy && i + 1 < chain.len() {
+                let checkpoint = Checkpoint {
+                    model,
+                    schedule_pos: i + 1,
+                    temp: point.temp,
+                };
+                if let Err(err) =
+                    self.save_checkpoint(&checkpoint_path, |path| checkpoint.save(path))
+                {
+                    eprintln!(
+                        "{}\ncould not create checkpoint for {}",
+                        err,
+                        config.chain_name(point)
+                    )
+                }
+                model = checkpoint.model;
+            }
+        }
+    }
+
+    /// Simulates all temperatures of the chain at once with parallel tempering.
+    /// The chain is checkpointed at the end of the equilibration, so a chain with missing points
+    /// only samples again if it was equilibrated before,
+    /// the trajectories of such a chain only contain the sampled sweeps
+    fn run_tempering(&self, chain: &[Point], logger: &CsvLogger) {
+        let config = &self.config;
+        let first = chain[0];
+        let sites = first.sites();
+        let checkpoint_path = self
+            .model_dir()
+            .join(format!("{}.checkpoint", config.chain_name(&first)));
+        let (mut tempering, mut eq_sweeps, equilibrated) =
+            match TemperingCheckpoint::load(&checkpoint_path) {
+                Ok(checkpoint) => (checkpoint.tempering, checkpoint.eq_sweeps, true),
+                Err(ModelError::Io(err)) if err.kind() == std::io::ErrorKind::NotFound => {
+                    let seed = config.chain_seed(&first);
+                    let replicas = (0..chain.len())
+                        .map(|i| {
+                            let seed = seed.as_ref().map(|seed| format!("{}_t_{}", seed, i));
+                            Model::new(
+                                first.size,
+                                first.couplings(),
+                                first.fill_frac,
+                                seed.as_deref(),
+                            )
+                        })
+                        .collect::<Result<_, _>>();
+                    let replicas = match replicas {
+                        Ok(replicas) => replicas,
+                        Err(err) => return self.fail(config.chain_name(&first), err.to_string()),
+                    };
+                    let tempering = ParallelTempering::new(
+                        replicas,
+                        chain.iter().map(|point| point.temp).collect(),
+                        config.chemical_potential,
+                        seed.as_deref(),
+                    );
+                    (tempering, 0, false)
+                }
+                Err(err) => {
+                    return self.fail(
+                        config.chain_name(&first),
+                        format!("could not resume from the checkpoint: {}", err),
+                    )
+                }
+            };
+
+        let mut recorders: Vec<_> = chain
+            .iter()
+            .zip(tempering.replicas())
+            .map(|(point, model)| self.recorders(point, model))
+            .collect();
+
+        // equilibration stops once every replica is stationary
+        let mut nothing = vec![(); chain.len()];
+        let mut detectors: Vec<_> = chain
+            .iter()
+            .map(|_| {
+                EquilibrationDetector::new(config.min_eq_epochs.div_ceil(config.exchange_interval))
+            })
+            .collect();
+        while !equilibrated && eq_sweeps < config.eq_epochs {
+            for _ in 0..config.exchange_interval {
+                tempering.steps(2 * sites, &mut nothing, |_, _| ());
+                self.record_replicas(&mut recorders, &tempering, false);
+            }
+            tempering.exchange();
+            self.sync(&mut recorders, &tempering);
+            eq_sweeps += config.exchange_interval;
+            if config.auto_equilibration {
+                for (detector, model) in detectors.iter_mut().zip(tempering.replicas()) {
+                    detector.add_value(model.get_hamiltonian() as f64);
+                }
+                if detectors[0].len() % CHECK_INTERVAL == 0
+                    && detectors.iter().all(|detector| detector.is_equilibrated())
+                {
+                    break;
+                }
+            }
+        }
+        if !equilibrated {
+            tempering.reset_counters();
+            let checkpoint = TemperingCheckpoint {
+                tempering,
+                eq_sweeps,
+            };
+            if let Err(err) = self.save_checkpoint(&checkpoint_path, |path| checkpoint.save(path)) {
+                eprintln!(
+                    "{}\ncould not create checkpoint for {}",
+                    err,
+                    config.chain_name(&first)
+                )
+            }
+            tempering = checkpoint.tempering;
+        }
+
+        let mut stats: Vec<_> = chain
+            .iter()
+            .map(|_| (StreamingStats::new(), StreamingStats::new()))
+            .collect();
+        for _ in 0..config.epoch / config.exchange_interval {
+            for _ in 0..config.exchange_interval {
+                tempering.steps(2 * sites, &mut nothing, |_, _| ());
+                for ((stats, fill_stats), model) in stats.iter_mut().zip(tempering.replicas()) {
+                    stats.add_value(model.get_hamiltonian() as f64);
+                    fill_stats.add_value(model.fill_frac())
+                }
+                self.record_replicas(&mut recorders, &tempering, true);
+            }
+            tempering.exchange();
+            self.sync(&mut recorders, &tempering);
+        }
+
+        let rates = tempering.acceptance_rates();
+        let replicas = chain.iter().zip(tempering.replicas()).zip(recorders);
+        for (i, ((point, model), recorders)) in replicas.enumerate() {
+            let (stats, fill_stats) = &stats[i];
+            let rate = rates.get(i).copied().unwrap_or(f32::NAN);
+            let row = self.row(point, stats, fill_stats, rate, eq_sweeps);
+            if let Err(err) = self.complete_point(point, model, row, recorders, logger) {
+                self.fail(config.point_name(point), err.to_string());
+            }
+        }
+    }
+
+    /// The row of the csv file of a point, energies, variances and heat capacities are given per site.
+    /// The errors are the standard errors from the binning analysis and the jackknife
+    /// and the autocorrelation time of the energy is given in sweeps.
+    /// The Binder cumulant is the fourth order cumulant of the energy
+    fn row(
+        &self,
+        point: &Point,
+        stats: &StreamingStats,
+        fill_stats: &StreamingStats,
+        swap_acceptance: f32,
+        eq_sweeps: usize,
+    ) -> Vec<f32> {
+        let sites = point.sites() as f64;
+        let (a, b, c) = point.size;
+        let temp = point.temp as f64;
+        let (heat_capacity, heat_capacity_err) =
+            stats.jackknife(|mean, mean_sq| (mean_sq - mean * mean) / (temp * temp));
+        vec![
+            point.j_prime,
+            point.temp,
+            (stats.avg() / sites) as f32,
+            (stats.variance() / sites) as f32,
+            fill_stats.avg() as f32,
+            swap_acceptance,
+            point.j_2,
+            point.fill_frac,
+            (a / 2) as f32,
+            (b / 2) as f32,
+            (c / 2) as f32,
+            eq_sweeps as f32,
+            (stats.error() / sites) as f32,
+            (heat_capacity / sites) as f32,
+            (heat_capacity_err / sites) as f32,
+            stats.autocorrelation_time() as f32,
+            stats.binder_cumulant() as f32,
+            point.j_3.unwrap_or(f32::NAN),
+            point.j_4.unwrap_or(f32::NAN),
+            point.j_5.unwrap_or(f32::NAN),
+        ]
+    }
+
+    /// Saves the model of a point, writes its row and the outputs of its recorders
+    /// and records it as completed. Points that are already completed are skipped
+    fn complete_point(
+        &self,
+        point: &Point,
+        model: &Model,
+        row: Vec<f32>,
+        recorders: Recorders,
+        logger: &CsvLogger,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.is_completed(point) {
+            return Ok(());
+        }
+        let mut files = self.save_model(point, model)?;
+        let out = Path::new(&self.config.output);
+        if let Some(trajectory) = recorders.trajectory {
+            trajectory.finish()?;
+            files.push(self.point_file("trajectories", point, "csv"));
+        }
+        if let Some(histogram) = recorders.histogram {
+            let file = self.point_file("histograms", point, "csv");
+            histogram
+                .write_to_csv(out.join(&file))
+                .map_err(|err| format!("could not write the histogram: {}", err))?;
+            files.push(file);
+        }
+        if let Some(short_range_order) = recorders.short_range_order {
+            let file = self.point_file("sro", point, "csv");
+            short_range_order
+                .write_to_csv(out.join(&file))
+                .map_err(|err| format!("could not write the short range order: {}", err))?;
+            files.push(file);
+        }
+        // an epoch shorter than the interval still gets the scattering of the final configuration
+        let scattering = recorders.scattering.or_else(|| {
+            self.config
+                .scattering_max_hkl
+                .map(|max_hkl| model.diffuse_scattering(max_hkl))
+        });
+        if let Some(scattering) = scattering {
+            let file = self.point_file("h5", point, "h5");
+            model
+                .write_scattering(&scattering, out.join(&file))
+                .map_err(|err| format!("could not write the diffuse scattering: {}", err))?;
+            files.push(file);
+            if let Some(plane) = &self.config.section {
+                let section = Section::new(
+                    &model.scattering_volume(&scattering),
+                    plane,
+                    self.config.symmetrize,
+                )?;
+                let csv = self.point_file("sections", point, "csv");
+                let png = self.point_file("sections", point, "png");
+                section
+                    .write_to_csv(out.join(&csv), plane)
+                    .and_then(|_| section.write_png(out.join(&png), &self.config.rendering()))
+                    .map_err(|err| format!("could not write the section: {}", err))?;
+                files.extend([csv, png]);
+            }
+        }
+        logger.send_row(row).map_err(|_| "the csv logger stopped")?;
+        self.update(|manifest| {
+            for file in files {
+                if !manifest.files.contains(&file) {
+                    manifest.files.push(file)
+                }
+            }
+            manifest.completed.push(*point)
+        })
+    }
+
+    /// Starts the enabled recorders of a point, there are none if the point is already completed
+    fn recorders(&self, point: &Point, model: &Model) -> Recorders {
+        let config = &self.config;
+        if self.is_completed(point) {
+            return Recorders::default();
+        }
+        let trajectory = config.trajectories.then(|| {
+            let path =
+                Path::new(&config.output).join(self.point_file("trajectories", point, "csv"));
+            Trajectory::new(
+                path.to_string_lossy().into_owned(),
+                &config.point_name(point),
+                model,
+                &self.order_parameters,
+            )
+        });
+        Recorders {
+            trajectory,
+            histogram: config
+                .histograms
+                .then(|| NeighbourHistogram::new(model, point.temp)),
+            short_range_order: config.sro_radius.map(ShortRangeOrder::new),
+            scattering: None,
+            samples: 0,
+        }
+    }
+
+    /// The path of the file of a point in one of the output directories, relative to the output
+    fn point_file(&self, directory: &str, point: &Point, extension: &str) -> String {
+        format!(
+            "{}/{}/{}.{}",
+            directory,
+            self.name,
+            self.config.point_name(point),
+            extension
+        )
+    }
+
+    /// Records the sweep that was just performed on the model
+    fn record(&self, recorders: &mut Recorders, model: &Model, sampling: bool) {
+        if let Some(trajectory) = &mut recorders.trajectory {
+            trajectory.record(model, sampling, &self.order_parameters)
+        }
+        if !sampling {
+            return;
+        }
+        recorders.samples += 1;
+        if let Some(histogram) = &mut recorders.histogram {
+            histogram.add(model)
+        }
+        if let Some(short_range_order) = &mut recorders.short_range_order {
+            if recorders.samples.is_multiple_of(self.config.sro_interval) {
+                short_range_order.add(model)
+            }
+        }
+        if let Some(max_hkl) = self.config.scattering_max_hkl {
+            if recorders
+                .samples
+                .is_multiple_of(self.config.scattering_interval)
+            {
+                let snapshot = model.diffuse_scattering(max_hkl);
+                match &mut recorders.scattering {
+                    Some(scattering) => scattering.merge(&snapshot),
+                    None => recorders.scattering = Some(snapshot),
+                }
+            }
+        }
+    }
+
+    /// Records the sweep of every replica with the recorders of its temperature
+    fn record_replicas(
+        &self,
+        recorders: &mut [Recorders],
+        tempering: &ParallelTempering,
+        sampling: bool,
+    ) {
+        for (recorders, model) in recorders.iter_mut().zip(tempering.replicas()) {
+            self.record(recorders, model, sampling)
+        }
+    }
+
+    /// Continues the trajectories with the replicas that were exchanged
+    fn sync(&self, recorders: &mut [Recorders], tempering: &ParallelTempering) {
+        for (recorders, model) in recorders.iter_mut().zip(tempering.replicas()) {
+            if let Some(trajectory) = &mut recorders.trajectory {
+                trajectory.sync(model)
+            }
+        }
+    }
+
+    /// Writes the model as mmcif and binary file and returns the paths relative to the output
+    fn save_model(&self, point: &Point, model: &Model) -> Result<Vec<String>, Box<dyn Error>> {
+        let out = Path::new(&self.config.output);
+        let name = self.config.point_name(point);
+        let mmcif = format!("mmcif/{}/{}.mmcif", self.name, name);
+        model
+            .write_to_cif(out.join(&mmcif))
+            .map_err(|err| format!("could not create mmcif: {}", err))?;
+        let binary = format!("models/{}/{}.bin", self.name, name);
+        model
+            .save_to_binary(out.join(&binary))
+            .map_err(|err| format!("could not create binary model: {}", err))?;
+        Ok(vec![mmcif, binary])
+    }
+
+    /// Writes the checkpoint to a temporary file first,
+    /// so a crash while writing does not destroy the previous checkpoint
+    fn save_checkpoint(
+        &self,
+        path: &Path,
+        save: impl FnOnce(&Path) -> std::io::Result<()>,
+    ) -> Result<(), Box<dyn Error>> {
+        let tmp = path.with_extension("checkpoint.tmp");
+        save(&tmp)?;
+        std::fs::rename(tmp, path)?;
+        let file = format!(
+            "models/{}/{}",
+            self.name,
+            path.file_name().unwrap_or_default().to_string_lossy()
+        );
+        let mut manifest = self.manifest.lock().unwrap();
+        if !manifest.files.contains(&file) {
+            manifest.files.push(file);
+            manifest.write_toml(self.model_dir().join("manifest.toml"))?;
+        }
+        Ok(())
+    }
+
+    /// Performs a single Monte Carlo step in the ensemble chosen by the chemical potential
+    fn step(&self, model: &mut Model, beta: f32) {
+        match self.config.chemical_potential {
+            Some(mu) => model.grand_canonical_step(beta, mu),
+            None => model.monte_carlo_step(beta),
+        }
+    }
+}
+
+/// Removes all rows from the csv file whose point is not completed
+/// and returns the points of the remaining rows.
+/// The removed rows were written before an interruption but their point is computed again
+fn prune_csv(path: &Path, completed: &[Point]) -> Result<Vec<Point>, Box<dyn Error>> {
+    let content = std::fs::read_to_string(path)?;
+    let mut out = String::new();
+    let mut rows = Vec::new();
+    // the 3 header lines and the column names
+    for (i, line) in content.lines().enumerate() {
+        let keep = i < 4 || {
+            let values: Vec<_> = line
+                .split(',')
+                .map(|val| val.parse::<f32>().unwrap_or(f32::NAN))
+                .collect();
+            // shells that are not included are written as NaN
+            let further = |value: f32| (!value.is_nan()).then_some(value);
+            let point = (values.len() == COLUMNS.len()).then(|| Point {
+                size: (
+                    2 * values[8] as usize,
+                    2 * values[9] as usize,
+                    2 * values[10] as usize,
+                ),
+                fill_frac: values[7],
+                j_2: values[6],
+                j_prime: values[0],
+                temp: values[1],
+                j_3: further(values[17]),
+                j_4: further(values[18]),
+                j_5: further(values[19]),
+            });
+            match point {
+                Some(point) if completed.contains(&point) => {
+                    rows.push(point);
+                    true
+                }
+                _ => false,
+            }
+        };
+        if keep {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    let tmp = path.with_extension("csv.tmp");
+    std::fs::write(&tmp, out)?;
+    std::fs::rename(tmp, path)?;
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Grid, SizeGrid};
+
+    /// The path of the csv file of a run
+    fn csv_path(out: &Path, run: &str) -> PathBuf {
+        out.join("csv").join(format!("{}.csv", run))
+    }
+
+    /// The sorted rows of the csv file of a run without the header and the column names
+    fn rows(out: &Path, run: &str) -> Vec<String> {
+        let csv = std::fs::read_to_string(csv_path(out, run)).unwrap();
+        let mut rows: Vec<String> = csv.lines().skip(4).map(String::from).collect();
+        rows.sort();
+        rows
+    }
+
+    #[test]
+    fn resume_recomputes_lost_rows() {
+        let out = std::env::temp_dir().join(format!("pba_resume_{}", std::process::id()));
+        let config = SweepConfig {
+            size: SizeGrid::Single((4, 4, 4)),
+            fill_frac: Grid::Single(0.5),
+            j_prime: Grid::Single(0.5),
+            temp: Grid::List(vec![3.0, 2.0, 1.0]),
+            eq_epochs: 20,
+            auto_equilibration: false,
+            epoch: 20,
+            seed: Some("resume".to_string()),
+            output: out.to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+        let runner = SweepRunner::new(config).unwrap();
+        let run = runner.name().to_string();
+        assert!(runner.run().unwrap().is_empty());
+        let complete = rows(&out, &run);
+        assert_eq!(complete.len(), 3);
+
+        // the first point of the chain lies before the checkpoint at the last point
+        let csv = std::fs::read_to_string(csv_path(&out, &run)).unwrap();
+        let lost: Vec<&str> = csv
+            .lines()
+            .filter(|line| !line.starts_with("0.5,3,"))
+            .collect();
+        assert_eq!(lost.len(), csv.lines().count() - 1);
+        std::fs::write(csv_path(&out, &run), lost.join("\n") + "\n").unwrap();
+
+        let runner = SweepRunner::resume(&out, &run).unwrap();
+        assert!(runner.run().unwrap().is_empty());
+        assert_eq!(rows(&out, &run), complete);
+        std::fs::remove_dir_all(&out).unwrap();
+    }
+}
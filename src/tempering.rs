@@ -0,0 +1,214 @@
+use rand::prelude::*;
+use rand_chacha::ChaCha12Rng;
+use rand_seeder::Seeder;
+use rayon::prelude::*;
+use std::io::Write;
+
+use crate::checkpoint::{parse_rng, write_rng};
+use crate::{Hamiltonian, LineReader, Model, ModelError, PairHamiltonian};
+
+/// A replica exchange driver.
+/// Every temperature has its own replica and configurations are periodically
+/// exchanged between neighbouring temperatures.
+pub struct ParallelTempering<H: Hamiltonian = PairHamiltonian> {
+    /// The replicas, replicas[i] is simulated at temps[i]
+    replicas: Vec<Model<H>>,
+    /// The temperatures in the order given to the constructor
+    temps: Vec<f32>,
+    /// The chemical potential if the replicas are sampled grand canonically
+    chemical_potential: Option<f32>,
+    /// The number of attempted exchanges between temps[i] and temps[i + 1]
+    attempted: Vec<u32>,
+    /// The number of accepted exchanges between temps[i] and temps[i + 1]
+    accepted: Vec<u32>,
+    /// Whether the next exchange starts with the pair (0, 1) or (1, 2)
+    odd_round: bool,
+    /// The random number generator for the exchanges
+    rng: ChaCha12Rng,
+}
+
+impl<H: Hamiltonian + Send> ParallelTempering<H> {
+    /// Constructor for the ParallelTempering driver.
+    /// There needs to be one replica for every temperature,
+    /// neighbouring temperatures in the list are exchanged.
+    pub fn new(
+        replicas: Vec<Model<H>>,
+        temps: Vec<f32>,
+        chemical_potential: Option<f32>,
+        seed: Option<&str>,
+    ) -> Self {
+        assert_eq!(
+            replicas.len(),
+            temps.len(),
+            "there needs to be one replica per temperature"
+        );
+        let rng = if let Some(seed) = seed {
+            Seeder::from(seed).make_rng()
+        } else {
+            ChaCha12Rng::from_entropy()
+        };
+        let pairs = temps.len().saturating_sub(1);
+        Self {
+            replicas,
+            temps,
+            chemical_potential,
+            attempted: vec![0; pairs],
+            accepted: vec![0; pairs],
+            odd_round: false,
+            rng,
+        }
+    }
+
+    /// Performs the given number of Monte Carlo steps on every replica in parallel.
+    /// After every step observe is called with the observer belonging to the temperature
+    /// and the replica currently at that temperature.
+    pub fn steps<T: Send>(
+        &mut self,
+        steps: usize,
+        observers: &mut [T],
+        observe: impl Fn(&mut T, &Model<H>) + Sync,
+    ) {
+        assert_eq!(observers.len(), self.temps.len());
+        let chemical_potential = self.chemical_potential;
+        self.replicas
+            .par_iter_mut()
+            .zip(&self.temps)
+            .zip(observers)
+            .for_each(|((model, temp), observer)| {
+                for _ in 0..steps {
+                    match chemical_potential {
+                        Some(mu) => model.grand_canonical_step(1.0 / temp, mu),
+                        None => model.monte_carlo_step(1.0 / temp),
+                    }
+                    observe(observer, model)
+                }
+            });
+    }
+
+    /// Attempts to exchange the configurations of neighbouring temperatures.
+    /// The rounds alternate between the pairs starting at even and odd indexes.
+    pub fn exchange(&mut self) {
+        let start = if self.odd_round { 1 } else { 0 };
+        self.odd_round = !self.odd_round;
+        for i in (start..self.attempted.len()).step_by(2) {
+            let beta_1 = 1.0 / self.temps[i];
+            let beta_2 = 1.0 / self.temps[i + 1];
+            let delta = (beta_1 - beta_2) * (self.reduced_energy(i) - self.reduced_energy(i + 1));
+            self.attempted[i] += 1;
+            if delta >= 0.0 || self.rng.gen::<f32>() < delta.exp() {
+                self.replicas.swap(i, i + 1);
+                self.accepted[i] += 1;
+            }
+        }
+    }
+
+    /// The energy entering the exchange criterion,
+    /// in the grand canonical case this includes the chemical potential
+    fn reduced_energy(&self, i: usize) -> f32 {
+        let model = &self.replicas[i];
+        match self.chemical_potential {
+            Some(mu) => model.get_hamiltonian() - mu * model.metalates() as f32,
+            None => model.get_hamiltonian(),
+        }
+    }
+
+    /// The fraction of accepted exchanges between temps[i] and temps[i + 1].
+    /// The rate of a pair without any attempted exchange is NaN,
+    /// which happens before the second call to exchange as the rounds alternate between the pairs
+    pub fn acceptance_rates(&self) -> Vec<f32> {
+        self.attempted
+            .iter()
+            .zip(&self.accepted)
+            .map(|(&attempted, &accepted)| {
+                if attempted == 0 {
+                    f32::NAN
+                } else {
+                    accepted as f32 / attempted as f32
+                }
+            })
+            .collect()
+    }
+
+    /// Resets the exchange counters
+    pub fn reset_counters(&mut self) {
+        self.attempted.iter_mut().for_each(|n| *n = 0);
+        self.accepted.iter_mut().for_each(|n| *n = 0);
+    }
+
+    /// Getter function for the temperatures
+    pub fn temps(&self) -> &[f32] {
+        &self.temps
+    }
+
+    /// Getter function for the replicas, replicas()[i] is at temps()[i]
+    pub fn replicas(&self) -> &[Model<H>] {
+        &self.replicas
+    }
+}
+
+impl ParallelTempering<PairHamiltonian> {
+    /// Writes the driver and all replicas including the states of their rngs.
+    /// The driver specific lines and the exchange counters of every pair come first,
+    /// they are followed by the replicas,
+    /// each consisting of its temperature, its rng and the model in the .txt format
+    pub(crate) fn write_txt(&self, file: &mut impl Write) -> std::io::Result<()> {
+        writeln!(file, "{} replicas", self.replicas.len())?;
+        match self.chemical_potential {
+            Some(mu) => writeln!(file, "{} chemical potential", mu)?,
+            None => writeln!(file, "none chemical potential")?,
+        }
+        writeln!(file, "{} odd round", self.odd_round)?;
+        for (attempted, accepted) in self.attempted.iter().zip(&self.accepted) {
+            writeln!(file, "{} attempted exchanges", attempted)?;
+            writeln!(file, "{} accepted exchanges", accepted)?;
+        }
+        write_rng(file, &self.rng)?;
+        for (model, temp) in self.replicas.iter().zip(&self.temps) {
+            writeln!(file, "{} temperature", temp)?;
+            write_rng(file, &model.rng)?;
+            model.write_txt(file)?;
+        }
+        Ok(())
+    }
+
+    /// Recreates the driver from the lines written by write_txt
+    pub(crate) fn parse_txt(reader: &mut LineReader) -> Result<Self, ModelError> {
+        let replicas: usize = reader.parse_next("replicas")?;
+        let chemical_potential = match reader.parse_next::<String>("chemical potential")? {
+            mu if mu == "none" => None,
+            mu => Some(mu.parse().map_err(|_| ModelError::InvalidField {
+                line: reader.line,
+                field: "chemical potential",
+                value: mu,
+            })?),
+        };
+        let odd_round = reader.parse_next("odd round")?;
+        let pairs = replicas.saturating_sub(1);
+        let mut attempted = Vec::with_capacity(pairs);
+        let mut accepted = Vec::with_capacity(pairs);
+        for _ in 0..pairs {
+            attempted.push(reader.parse_next("attempted exchanges")?);
+            accepted.push(reader.parse_next("accepted exchanges")?);
+        }
+        let rng = parse_rng(reader)?;
+
+        let mut models = Vec::with_capacity(replicas);
+        let mut temps = Vec::with_capacity(replicas);
+        for _ in 0..replicas {
+            temps.push(reader.parse_next("temperature")?);
+            let rng = parse_rng(reader)?;
+            let mut model = Model::parse_txt(reader)?;
+            model.rng = rng;
+            models.push(model);
+        }
+        Ok(Self {
+            replicas: models,
+            temps,
+            chemical_potential,
+            attempted,
+            accepted,
+            odd_round,
+            rng,
+        })
+    }
+}
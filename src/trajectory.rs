@@ -0,0 +1,162 @@
+use std::error::Error;
+use std::thread::JoinHandle;
+
+use crate::{CsvLogger, Model};
+
+/// A named observable of a model that is recorded in every row of the trajectories,
+/// e.g. a custom order parameter
+pub struct OrderParameter {
+    pub name: String,
+    pub measure: Box<dyn Fn(&Model) -> f64 + Send + Sync>,
+}
+
+/// The time series of the observables of a single point with one row per sweep,
+/// which shows if the model got stuck in a metastable state.
+/// The rows contain the index of the sweep, if it was sampled or part of the equilibration,
+/// the energy per site, the neighbour sums of all shells, the moves of the sweep,
+/// the fill fraction and the order parameters
+pub struct Trajectory {
+    logger: CsvLogger,
+    handle: JoinHandle<std::io::Result<()>>,
+    sweep: usize,
+    /// The move counters of the model at the start of the sweep
+    counters: (u32, u32, u32),
+}
+
+impl Trajectory {
+    /// Starts writing the trajectory of the model to the csv file at path
+    pub fn new(
+        path: String,
+        name: &str,
+        model: &Model,
+        order_parameters: &[OrderParameter],
+    ) -> Self {
+        let mut columns = vec![
+            "sweep".to_string(),
+            "sampling".to_string(),
+            "energy".to_string(),
+        ];
+        columns.extend(
+            (1..=model.hamiltonian().neighbour_sums().len()).map(|n| format!("neighbours_{}", n)),
+        );
+        columns
+            .extend(["good_moves", "bad_moves", "rejected_moves", "fill_frac"].map(String::from));
+        columns.extend(
+            order_parameters
+                .iter()
+                .map(|parameter| parameter.name.clone()),
+        );
+        let header = format!(
+            "trajectory of {}\nenergy is given per cyanometalate site, neighbour sums are totals\nthe moves are counted per sweep, sampling is 0 during the equilibration",
+            name
+        );
+        let (logger, handle) = CsvLogger::new(path, header, columns);
+        Self {
+            logger,
+            handle,
+            sweep: 0,
+            counters: model.counters(),
+        }
+    }
+
+    /// Writes the row of the sweep that was just performed on the model
+    pub fn record(&mut self, model: &Model, sampling: bool, order_parameters: &[OrderParameter]) {
+        self.sweep += 1;
+        let (good, bad, rejected) = model.counters();
+        let mut row = vec![
+            self.sweep as f32,
+            sampling as u8 as f32,
+            model.get_hamiltonian() / model.sites() as f32,
+        ];
+        row.extend(
+            model
+                .hamiltonian()
+                .neighbour_sums()
+                .iter()
+                .map(|sum| *sum as f32),
+        );
+        row.extend([
+            good.wrapping_sub(self.counters.0) as f32,
+            bad.wrapping_sub(self.counters.1) as f32,
+            rejected.wrapping_sub(self.counters.2) as f32,
+            model.fill_frac() as f32,
+        ]);
+        row.extend(
+            order_parameters
+                .iter()
+                .map(|parameter| (parameter.measure)(model) as f32),
+        );
+        // a logger that stopped reports its error in finish
+        let _ = self.logger.send_row(row);
+        self.counters = (good, bad, rejected);
+    }
+
+    /// Continues the trajectory with another model at the same point,
+    /// e.g. after an exchange of parallel tempering
+    pub fn sync(&mut self, model: &Model) {
+        self.counters = model.counters();
+    }
+
+    /// Waits until all rows are written
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        drop(self.logger);
+        self.handle
+            .join()
+            .map_err(|_| "the trajectory logger panicked")?
+            .map_err(|err| format!("could not write the trajectory: {}", err).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_row_per_sweep() {
+        let path = std::env::temp_dir().join(format!("pba_trajectory_{}.csv", std::process::id()));
+        let mut model = Model::new((4, 4, 4), vec![-1.0, 0.5], 0.5, Some("trajectory")).unwrap();
+        let order_parameters = [OrderParameter {
+            name: "vacancies".to_string(),
+            measure: Box::new(|model: &Model| (model.sites() - model.metalates()) as f64),
+        }];
+        let mut trajectory = Trajectory::new(
+            path.to_string_lossy().into_owned(),
+            "test",
+            &model,
+            &order_parameters,
+        );
+        let steps = 2 * model.sites();
+        for sweep in 0..5 {
+            for _ in 0..steps {
+                model.monte_carlo_step(1.0);
+            }
+            trajectory.record(&model, sweep >= 3, &order_parameters);
+        }
+        trajectory.finish().unwrap();
+
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(
+            lines[3],
+            "sweep,sampling,energy,neighbours_1,neighbours_2,good_moves,bad_moves,rejected_moves,fill_frac,vacancies"
+        );
+        let rows: Vec<Vec<f32>> = lines[4..]
+            .iter()
+            .map(|line| line.split(',').map(|v| v.parse().unwrap()).collect())
+            .collect();
+        assert_eq!(rows.len(), 5);
+        for (n, row) in rows.iter().enumerate() {
+            assert_eq!(row[0], (n + 1) as f32);
+            assert_eq!(row[1], if n >= 3 { 1.0 } else { 0.0 });
+            // every step is a good, bad or rejected move
+            assert_eq!(row[5] + row[6] + row[7], steps as f32);
+            assert_eq!(row[8], 0.5);
+            assert_eq!(row[9], 16.0);
+        }
+        let last = rows.last().unwrap();
+        assert_eq!(last[2], model.get_hamiltonian() / 32.0);
+        let sums = model.hamiltonian().neighbour_sums();
+        assert_eq!(last[3..5], [sums[0] as f32, sums[1] as f32]);
+    }
+}
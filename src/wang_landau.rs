@@ -0,0 +1,368 @@
+use rand::prelude::*;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+
+use crate::{Hamiltonian, Index, LineReader, Model, ModelError, PairHamiltonian};
+
+/// A Wang-Landau sampler for the joint density of states of the neighbour sums.
+/// Because the energy only depends on the neighbour sums of the shells,
+/// g(N_1, N_2, ...) allows to evaluate the thermodynamics for any couplings afterwards.
+pub struct WangLandau {
+    /// The model performing the random walk, its couplings are ignored
+    model: Model<PairHamiltonian>,
+    /// The current estimate of the logarithm of the density of states
+    ln_g: HashMap<Vec<i64>, f64>,
+    /// The visits of every state in the current stage
+    histogram: HashMap<Vec<i64>, u64>,
+    /// The logarithm of the modification factor
+    ln_f: f64,
+    /// The histogram is flat if every entry is at least flatness times the mean
+    flatness: f64,
+}
+
+impl WangLandau {
+    /// Constructor for the WangLandau sampler.
+    /// The composition of the model stays fixed during the random walk.
+    pub fn new(model: Model<PairHamiltonian>, flatness: f64) -> Self {
+        Self {
+            model,
+            ln_g: HashMap::new(),
+            histogram: HashMap::new(),
+            ln_f: 1.0,
+            flatness,
+        }
+    }
+
+    /// Performs a single step of the random walk in the space of neighbour sums
+    fn step(&mut self) {
+        if let Some((idx_1, idx_2)) = self.model.choose_swap_pos() {
+            self.swap_step(idx_1, idx_2);
+        }
+        let state = self.model.hamiltonian.neighbour_sums().to_vec();
+        *self.ln_g.entry(state.clone()).or_insert(0.0) += self.ln_f;
+        *self.histogram.entry(state).or_insert(0) += 1;
+    }
+
+    /// Swaps the two sites with the acceptance probability of the random walk
+    fn swap_step(&mut self, idx_1: Index, idx_2: Index) {
+        let old = self
+            .model
+            .hamiltonian
+            .local(&self.model.grid, &[idx_1, idx_2]);
+
+        self.model.swap(idx_1, idx_2);
+
+        let new = self
+            .model
+            .hamiltonian
+            .local(&self.model.grid, &[idx_1, idx_2]);
+
+        let old_state = self.model.hamiltonian.neighbour_sums().to_vec();
+        let new_state: Vec<i64> = old_state
+            .iter()
+            .zip(old.iter().zip(&new))
+            .map(|(sum, (old, new))| sum + new - old)
+            .collect();
+
+        let ln_g_old = self.ln_g.get(&old_state).copied().unwrap_or(0.0);
+        let ln_g_new = self.ln_g.get(&new_state).copied().unwrap_or(0.0);
+
+        if ln_g_new <= ln_g_old || self.model.rng.gen::<f64>() < (ln_g_old - ln_g_new).exp() {
+            self.model.hamiltonian.accept(&old, &new);
+            self.model.good_moves += 1;
+        } else {
+            self.model.swap(idx_1, idx_2);
+            self.model.rejected_moves += 1;
+        }
+    }
+
+    /// Checks if the histogram of the current stage is flat.
+    /// All states found so far count, the ones not visited in this stage with 0 visits
+    fn is_flat(&self) -> bool {
+        if self.ln_g.is_empty() {
+            return false;
+        }
+        let visits = |state| self.histogram.get(state).copied().unwrap_or(0);
+        let mean = self.histogram.values().sum::<u64>() as f64 / self.ln_g.len() as f64;
+        let min = self
+            .ln_g
+            .keys()
+            .map(visits)
+            .min()
+            .expect("ln_g is not empty") as f64;
+        min >= self.flatness * mean
+    }
+
+    /// Runs the random walk until the logarithm of the modification factor is below ln_f_final.
+    /// The flatness of the histogram is checked every check_interval sweeps
+    /// and the modification factor is halved whenever it is flat.
+    /// Fails if ln_f_final is not reached within max_sweeps sweeps.
+    pub fn run(
+        &mut self,
+        ln_f_final: f64,
+        check_interval: usize,
+        max_sweeps: usize,
+    ) -> Result<DensityOfStates, String> {
+        let steps = 2 * self.model.sites();
+        let mut sweeps = 0;
+        while self.ln_f > ln_f_final {
+            if sweeps >= max_sweeps {
+                return Err(format!(
+                    "the modification factor only reached ln_f = {} in {} sweeps",
+                    self.ln_f, sweeps
+                ));
+            }
+            for _ in 0..check_interval {
+                for _ in 0..steps {
+                    self.step()
+                }
+            }
+            sweeps += check_interval;
+            if self.is_flat() {
+                self.ln_f /= 2.0;
+                self.histogram.clear();
+            }
+        }
+        Ok(DensityOfStates::new(
+            self.ln_g.clone().into_iter().collect(),
+            self.model.sites(),
+            self.model.metalates(),
+        ))
+    }
+
+    /// Getter function for the current logarithm of the modification factor
+    pub fn ln_f(&self) -> f64 {
+        self.ln_f
+    }
+}
+
+/// The thermodynamic quantities at a single temperature.
+/// All values are given for the whole model.
+#[derive(Clone, Copy, Debug)]
+pub struct Thermodynamics {
+    pub temp: f64,
+    pub free_energy: f64,
+    pub energy: f64,
+    pub entropy: f64,
+    pub heat_capacity: f64,
+}
+
+/// The density of states as a function of the neighbour sums of all shells
+#[derive(Clone, Debug)]
+pub struct DensityOfStates {
+    /// The neighbour sums of every state and the logarithm of their degeneracy
+    ln_g: Vec<(Vec<i64>, f64)>,
+    /// The number of cyanometalate sites
+    sites: usize,
+    /// The number of cyanometalates
+    metalates: usize,
+}
+
+impl DensityOfStates {
+    /// Constructor for the DensityOfStates.
+    /// The density is normalized such that the sum over all states
+    /// is the number of ways to distribute the cyanometalates on the sites.
+    pub fn new(mut ln_g: Vec<(Vec<i64>, f64)>, sites: usize, metalates: usize) -> Self {
+        ln_g.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let ln_total = ln_binomial(sites, metalates);
+        let ln_sum = log_sum_exp(ln_g.iter().map(|(_, ln_g)| *ln_g));
+        for (_, value) in ln_g.iter_mut() {
+            *value += ln_total - ln_sum;
+        }
+        Self {
+            ln_g,
+            sites,
+            metalates,
+        }
+    }
+
+    /// Getter function for the neighbour sums and the logarithm of their degeneracy
+    pub fn ln_g(&self) -> &[(Vec<i64>, f64)] {
+        &self.ln_g
+    }
+
+    /// Getter function for the number of cyanometalate sites
+    pub fn sites(&self) -> usize {
+        self.sites
+    }
+
+    /// Getter function for the number of cyanometalates
+    pub fn metalates(&self) -> usize {
+        self.metalates
+    }
+
+    /// The energy of a state with the given neighbour sums
+    fn energy(couplings: &[f32], sums: &[i64]) -> f64 {
+        couplings
+            .iter()
+            .zip(sums)
+            .map(|(j, sum)| *j as f64 * *sum as f64)
+            .sum()
+    }
+
+    /// Calculates the thermodynamic quantities for the given couplings at temperature temp
+    pub fn thermodynamics(&self, couplings: &[f32], temp: f64) -> Thermodynamics {
+        let beta = 1.0 / temp;
+        let energies: Vec<f64> = self
+            .ln_g
+            .iter()
+            .map(|(sums, _)| Self::energy(couplings, sums))
+            .collect();
+        let ln_weights: Vec<f64> = self
+            .ln_g
+            .iter()
+            .zip(&energies)
+            .map(|((_, ln_g), energy)| ln_g - beta * energy)
+            .collect();
+        let ln_z = log_sum_exp(ln_weights.iter().copied());
+
+        let mut energy = 0.0;
+        let mut energy_sq = 0.0;
+        for (ln_weight, e) in ln_weights.iter().zip(&energies) {
+            let p = (ln_weight - ln_z).exp();
+            energy += p * e;
+            energy_sq += p * e * e;
+        }
+        let free_energy = -temp * ln_z;
+        Thermodynamics {
+            temp,
+            free_energy,
+            energy,
+            entropy: (energy - free_energy) / temp,
+            heat_capacity: (energy_sq - energy * energy) / (temp * temp),
+        }
+    }
+
+    /// Writes the density of states to a csv file
+    pub fn write_to_csv(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let mut file = std::fs::File::create(path)?;
+        writeln!(file, "{} sites", self.sites)?;
+        writeln!(file, "{} metalates", self.metalates)?;
+        let shells = self.ln_g.first().map(|(sums, _)| sums.len()).unwrap_or(0);
+        let mut columns: Vec<String> = (1..=shells).map(|n| format!("n_{}", n)).collect();
+        columns.push("ln_g".to_string());
+        writeln!(file, "{}", columns.join(","))?;
+        for (sums, ln_g) in &self.ln_g {
+            for sum in sums {
+                write!(file, "{},", sum)?;
+            }
+            writeln!(file, "{}", ln_g)?;
+        }
+        file.flush()
+    }
+
+    /// Reads a density of states written by write_to_csv
+    pub fn from_csv(path: impl AsRef<Path>) -> Result<Self, ModelError> {
+        let string = std::fs::read_to_string(path)?;
+        let mut reader = LineReader::new(&string);
+        let sites = reader.parse_next("sites")?;
+        let metalates = reader.parse_next("metalates")?;
+        let columns = reader.next_line("column names")?.split(',').count();
+        let mut ln_g = Vec::new();
+        while let Ok(line) = reader.next_line("density of states") {
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = |value: &str| ModelError::InvalidField {
+                line: reader.line,
+                field: "density of states",
+                value: value.to_string(),
+            };
+            let values: Vec<&str> = line.split(',').collect();
+            if values.len() != columns {
+                return Err(invalid(line));
+            }
+            let (sums, value) = values.split_at(columns - 1);
+            let sums = sums
+                .iter()
+                .map(|s| s.parse().map_err(|_| invalid(s)))
+                .collect::<Result<Vec<i64>, _>>()?;
+            ln_g.push((sums, value[0].parse().map_err(|_| invalid(value[0]))?));
+        }
+        Ok(Self {
+            ln_g,
+            sites,
+            metalates,
+        })
+    }
+}
+
+/// The logarithm of the binomial coefficient n choose k
+fn ln_binomial(n: usize, k: usize) -> f64 {
+    let k = k.min(n - k);
+    (0..k)
+        .map(|i| ((n - i) as f64).ln() - ((i + 1) as f64).ln())
+        .sum()
+}
+
+/// Calculates ln(sum(exp(x))) without overflow
+pub(crate) fn log_sum_exp(values: impl Iterator<Item = f64> + Clone) -> f64 {
+    let max = values.clone().fold(f64::NEG_INFINITY, f64::max);
+    if max == f64::NEG_INFINITY {
+        return max;
+    }
+    max + values.map(|x| (x - max).exp()).sum::<f64>().ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cyanometalate_sites, Array3d};
+
+    const DIMS: (usize, usize, usize) = (4, 4, 4);
+
+    /// The number of configurations of 3 cyanometalates with every pair of neighbour sums
+    fn exact_degeneracies() -> HashMap<Vec<i64>, f64> {
+        let (a, b, c) = DIMS;
+        let sites: Vec<Index> = cyanometalate_sites(DIMS).collect();
+        let mut hamiltonian = PairHamiltonian::new(vec![0.0; 2]);
+        let mut degeneracies = HashMap::new();
+        for i in 0..sites.len() {
+            for j in i + 1..sites.len() {
+                for k in j + 1..sites.len() {
+                    let mut grid = Array3d::new(a, b, c);
+                    for idx in &sites {
+                        grid[*idx] = -1;
+                    }
+                    for n in [i, j, k] {
+                        grid[sites[n]] = 1;
+                    }
+                    hamiltonian.calc_sums(&grid);
+                    *degeneracies
+                        .entry(hamiltonian.neighbour_sums().to_vec())
+                        .or_insert(0.0) += 1.0;
+                }
+            }
+        }
+        degeneracies
+    }
+
+    #[test]
+    fn recovers_the_density_of_states() {
+        // 3 of the 32 sites are occupied
+        let model = Model::new(DIMS, vec![0.0; 2], 0.1, Some("wang_landau")).unwrap();
+        let density = WangLandau::new(model, 0.8)
+            .run(1e-4, 20, 1_000_000)
+            .unwrap();
+        assert_eq!(density.metalates(), 3);
+        let exact = exact_degeneracies();
+        assert_eq!(density.ln_g().len(), exact.len());
+        for (sums, ln_g) in density.ln_g() {
+            let expected = exact[sums].ln();
+            assert!(
+                (ln_g - expected).abs() < 0.1,
+                "{:?}: {} != {}",
+                sums,
+                ln_g,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn reports_missing_convergence() {
+        let model = Model::new(DIMS, vec![0.0; 2], 0.1, Some("wang_landau")).unwrap();
+        assert!(WangLandau::new(model, 0.8).run(1e-6, 10, 10).is_err());
+    }
+}
@@ -0,0 +1,563 @@
+use std::io::Write;
+use std::path::Path;
+
+use crate::Array3d;
+
+/// A volume in the layout of the Yell .h5 files, either intensities in reciprocal space
+/// or a pair distribution function in direct space.
+/// The data is given with h (or u) along the first index of the array,
+/// so a point (i, j, k) lies at lower_limits + (i, j, k) * step_sizes.
+pub struct YellVolume<'a> {
+    pub data: &'a Array3d<f64>,
+    pub lower_limits: [f64; 3],
+    pub step_sizes: [f64; 3],
+    /// a, b, c in Ångström and alpha, beta, gamma in degrees
+    pub unit_cell: [f64; 6],
+    pub is_direct: bool,
+}
+
+impl YellVolume<'_> {
+    /// Writes the volume to a HDF5 file with the datasets data, format, is_direct,
+    /// lower_limits, step_sizes and unit_cell like `Diffraction.save_yell` in python.
+    /// The data is stored in C order, so the last index runs fastest
+    pub fn write(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let (width, height, depth) = self.data.dims();
+        let mut data = Vec::with_capacity(8 * width * height * depth);
+        for i in 0..width as isize {
+            for j in 0..height as isize {
+                for k in 0..depth as isize {
+                    data.extend(self.data[(i, j, k)].to_le_bytes());
+                }
+            }
+        }
+        let floats = |values: &[f64]| values.iter().flat_map(|x| x.to_le_bytes()).collect();
+        // the datasets need to be sorted by name for the symbol table
+        let datasets = [
+            Dataset {
+                name: "data",
+                datatype: Datatype::Float,
+                dims: vec![width as u64, height as u64, depth as u64],
+                data,
+            },
+            Dataset {
+                name: "format",
+                datatype: Datatype::String(8),
+                dims: vec![],
+                data: b"Yell 1.0".to_vec(),
+            },
+            Dataset {
+                name: "is_direct",
+                datatype: Datatype::Integer,
+                dims: vec![],
+                data: (self.is_direct as i64).to_le_bytes().to_vec(),
+            },
+            Dataset {
+                name: "lower_limits",
+                datatype: Datatype::Float,
+                dims: vec![3],
+                data: floats(&self.lower_limits),
+            },
+            Dataset {
+                name: "step_sizes",
+                datatype: Datatype::Float,
+                dims: vec![3],
+                data: floats(&self.step_sizes),
+            },
+            Dataset {
+                name: "unit_cell",
+                datatype: Datatype::Float,
+                dims: vec![6],
+                data: floats(&self.unit_cell),
+            },
+        ];
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        file.write_all(&hdf5_file(&datasets))?;
+        file.flush()
+    }
+}
+
+/// The undefined address of HDF5
+const UNDEFINED: u64 = u64::MAX;
+/// The group internal node K of the superblock, which sets the size of the B-tree nodes
+const INTERNAL_K: usize = 16;
+/// The group leaf node K of the superblock, a symbol table node holds up to 2K entries
+const LEAF_K: usize = 4;
+const SUPERBLOCK_SIZE: usize = 96;
+const LOCAL_HEAP_HEADER_SIZE: usize = 32;
+const BTREE_NODE_SIZE: usize = 24 + 2 * INTERNAL_K * 8 + (2 * INTERNAL_K + 1) * 8;
+const SYMBOL_TABLE_NODE_SIZE: usize = 8 + 2 * LEAF_K * SYMBOL_TABLE_ENTRY_SIZE;
+const SYMBOL_TABLE_ENTRY_SIZE: usize = 40;
+
+/// The element types of the datasets, all little endian
+enum Datatype {
+    /// 64 bit IEEE floats
+    Float,
+    /// 64 bit signed integers
+    Integer,
+    /// Null padded ASCII strings of the given length
+    String(u32),
+}
+
+/// A contiguous dataset in the root group, a scalar if dims is empty
+struct Dataset {
+    name: &'static str,
+    datatype: Datatype,
+    dims: Vec<u64>,
+    data: Vec<u8>,
+}
+
+impl Dataset {
+    /// The messages of the object header as (type, flags, data)
+    fn messages(&self, data_address: u64) -> Vec<(u16, u8, Vec<u8>)> {
+        // dataspace, version 1 without maximum dimensions
+        let mut dataspace = vec![1, self.dims.len() as u8, 0, 0, 0, 0, 0, 0];
+        for dim in &self.dims {
+            dataspace.extend(dim.to_le_bytes());
+        }
+        let datatype = match self.datatype {
+            Datatype::Float => {
+                // little endian, implied mantissa msb and the sign at bit 63
+                let mut datatype = vec![0x11, 0x20, 63, 0];
+                datatype.extend(8u32.to_le_bytes());
+                datatype.extend(0u16.to_le_bytes());
+                datatype.extend(64u16.to_le_bytes());
+                datatype.extend([52, 11, 0, 52]);
+                datatype.extend(1023u32.to_le_bytes());
+                datatype
+            }
+            Datatype::Integer => {
+                let mut datatype = vec![0x10, 0x08, 0, 0];
+                datatype.extend(8u32.to_le_bytes());
+                datatype.extend(0u16.to_le_bytes());
+                datatype.extend(64u16.to_le_bytes());
+                datatype
+            }
+            Datatype::String(len) => {
+                let mut datatype = vec![0x13, 0x01, 0, 0];
+                datatype.extend(len.to_le_bytes());
+                datatype
+            }
+        };
+        // version 2, allocated late, written on allocation, no fill value defined
+        let fill_value = vec![2, 2, 0, 0];
+        // version 3, contiguous
+        let mut layout = vec![3, 1];
+        layout.extend(data_address.to_le_bytes());
+        layout.extend((self.data.len() as u64).to_le_bytes());
+        vec![
+            (0x0001, 0, dataspace),
+            (0x0003, 1, datatype),
+            (0x0005, 1, fill_value),
+            (0x0008, 0, layout),
+        ]
+    }
+}
+
+/// The size of a version 1 object header with messages of the given sizes
+fn object_header_size(message_sizes: impl Iterator<Item = usize>) -> usize {
+    16 + message_sizes
+        .map(|size| 8 + size.next_multiple_of(8))
+        .sum::<usize>()
+}
+
+/// A version 1 object header, the messages are padded to multiples of 8 bytes
+fn object_header(messages: &[(u16, u8, Vec<u8>)]) -> Vec<u8> {
+    let size = object_header_size(messages.iter().map(|(_, _, data)| data.len())) - 16;
+    let mut header = vec![1, 0];
+    header.extend((messages.len() as u16).to_le_bytes());
+    header.extend(1u32.to_le_bytes());
+    header.extend((size as u32).to_le_bytes());
+    header.extend([0; 4]);
+    for (kind, flags, data) in messages {
+        let padded = data.len().next_multiple_of(8);
+        header.extend(kind.to_le_bytes());
+        header.extend((padded as u16).to_le_bytes());
+        header.extend([*flags, 0, 0, 0]);
+        header.extend(data);
+        header.resize(header.len() + padded - data.len(), 0);
+    }
+    header
+}
+
+/// A symbol table entry pointing to an object header, with the scratch pad of a group if given
+fn symbol_table_entry(name_offset: u64, address: u64, group: Option<(u64, u64)>) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(SYMBOL_TABLE_ENTRY_SIZE);
+    entry.extend(name_offset.to_le_bytes());
+    entry.extend(address.to_le_bytes());
+    entry.extend((group.is_some() as u32).to_le_bytes());
+    entry.extend([0; 4]);
+    let (btree, heap) = group.unwrap_or((0, 0));
+    entry.extend(btree.to_le_bytes());
+    entry.extend(heap.to_le_bytes());
+    entry
+}
+
+/// A HDF5 file with superblock version 0 whose root group contains the datasets.
+/// The root group is an old style group with a local heap for the names,
+/// a B-tree with a single leaf and a single symbol table node.
+/// The datasets need to be sorted by name and there can be at most 2 * LEAF_K of them
+fn hdf5_file(datasets: &[Dataset]) -> Vec<u8> {
+    assert!(datasets.len() <= 2 * LEAF_K);
+    assert!(datasets.windows(2).all(|pair| pair[0].name < pair[1].name));
+
+    // the names in the local heap start after the empty name of the root group
+    let mut heap = vec![0; 8];
+    let mut name_offsets = Vec::new();
+    for dataset in datasets {
+        name_offsets.push(heap.len() as u64);
+        heap.extend(dataset.name.as_bytes());
+        heap.resize((heap.len() + 1).next_multiple_of(8), 0);
+    }
+
+    let root_header_address = SUPERBLOCK_SIZE;
+    let heap_address = root_header_address + object_header_size([16].into_iter());
+    let heap_data_address = heap_address + LOCAL_HEAP_HEADER_SIZE;
+    let btree_address = heap_data_address + heap.len();
+    let symbol_table_node_address = btree_address + BTREE_NODE_SIZE;
+    let mut address = symbol_table_node_address + SYMBOL_TABLE_NODE_SIZE;
+    let mut header_addresses = Vec::new();
+    let mut data_addresses = Vec::new();
+    for dataset in datasets {
+        header_addresses.push(address as u64);
+        // the size of the messages does not depend on the address of the data
+        address += object_header(&dataset.messages(0)).len();
+        data_addresses.push(address as u64);
+        address += dataset.data.len().next_multiple_of(8);
+    }
+    let end_of_file = address as u64;
+    let (btree_address, heap_address) = (btree_address as u64, heap_address as u64);
+
+    let mut file = Vec::with_capacity(address);
+    // superblock
+    file.extend(b"\x89HDF\r\n\x1a\n");
+    file.extend([0, 0, 0, 0, 0, 8, 8, 0]);
+    file.extend((LEAF_K as u16).to_le_bytes());
+    file.extend((INTERNAL_K as u16).to_le_bytes());
+    file.extend(0u32.to_le_bytes());
+    for value in [0, UNDEFINED, end_of_file, UNDEFINED] {
+        file.extend(value.to_le_bytes());
+    }
+    file.extend(symbol_table_entry(
+        0,
+        root_header_address as u64,
+        Some((btree_address, heap_address)),
+    ));
+
+    // root group with a symbol table message
+    let mut symbol_table = btree_address.to_le_bytes().to_vec();
+    symbol_table.extend(heap_address.to_le_bytes());
+    file.extend(object_header(&[(0x0011, 0, symbol_table)]));
+
+    // local heap without free blocks, which the library marks with an offset of 1
+    file.extend(b"HEAP");
+    file.extend([0, 0, 0, 0]);
+    file.extend((heap.len() as u64).to_le_bytes());
+    file.extend(1u64.to_le_bytes());
+    file.extend((heap_data_address as u64).to_le_bytes());
+    file.extend(&heap);
+
+    // B-tree leaf pointing to the symbol table node, keys are offsets into the heap
+    let node_start = file.len();
+    file.extend(b"TREE");
+    file.extend([0, 0]);
+    file.extend(1u16.to_le_bytes());
+    file.extend(UNDEFINED.to_le_bytes());
+    file.extend(UNDEFINED.to_le_bytes());
+    file.extend(0u64.to_le_bytes());
+    file.extend((symbol_table_node_address as u64).to_le_bytes());
+    file.extend(name_offsets.last().copied().unwrap_or(0).to_le_bytes());
+    file.resize(node_start + BTREE_NODE_SIZE, 0);
+
+    // symbol table node with the datasets
+    let node_start = file.len();
+    file.extend(b"SNOD");
+    file.extend([1, 0]);
+    file.extend((datasets.len() as u16).to_le_bytes());
+    for (name_offset, address) in name_offsets.iter().zip(&header_addresses) {
+        file.extend(symbol_table_entry(*name_offset, *address, None));
+    }
+    file.resize(node_start + SYMBOL_TABLE_NODE_SIZE, 0);
+
+    for (dataset, data_address) in datasets.iter().zip(data_addresses) {
+        file.extend(object_header(&dataset.messages(data_address)));
+        file.extend(&dataset.data);
+        file.resize(file.len().next_multiple_of(8), 0);
+    }
+    debug_assert_eq!(file.len() as u64, end_of_file);
+    file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads little endian integers at an offset of the file
+    fn read_u64(file: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(file[offset..offset + 8].try_into().unwrap())
+    }
+
+    fn read_u32(file: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(file[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn read_u16(file: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(file[offset..offset + 2].try_into().unwrap())
+    }
+
+    /// The messages of a version 1 object header as (type, data),
+    /// checking that they fill the header exactly
+    fn read_object_header(file: &[u8], address: usize) -> Vec<(u16, &[u8])> {
+        assert_eq!(file[address], 1, "object header version");
+        let count = read_u16(file, address + 2) as usize;
+        let size = read_u32(file, address + 8) as usize;
+        let mut messages = Vec::new();
+        let mut offset = address + 16;
+        while offset < address + 16 + size {
+            let kind = read_u16(file, offset);
+            let len = read_u16(file, offset + 2) as usize;
+            assert_eq!(len % 8, 0, "messages are aligned to 8 bytes");
+            messages.push((kind, &file[offset + 8..offset + 8 + len]));
+            offset += 8 + len;
+        }
+        assert_eq!(offset, address + 16 + size);
+        assert_eq!(messages.len(), count);
+        messages
+    }
+
+    /// A dataset read back by following the structures of the HDF5 file format specification
+    /// from the superblock through the root group to the contiguous data
+    #[derive(Debug, PartialEq)]
+    struct ReadDataset {
+        name: String,
+        /// The class of the datatype, 0 for integers, 1 for floats and 3 for strings
+        class: u8,
+        size: u32,
+        dims: Vec<u64>,
+        data: Vec<u8>,
+    }
+
+    fn read_hdf5(file: &[u8]) -> Vec<ReadDataset> {
+        assert_eq!(&file[..8], b"\x89HDF\r\n\x1a\n");
+        assert_eq!(file[8], 0, "superblock version");
+        assert_eq!((file[13], file[14]), (8, 8), "sizes of offsets and lengths");
+        assert_eq!(read_u16(file, 16) as usize, LEAF_K);
+        assert_eq!(read_u16(file, 18) as usize, INTERNAL_K);
+        assert_eq!(read_u64(file, 24), 0, "base address");
+        assert_eq!(read_u64(file, 40), file.len() as u64, "end of file address");
+        // the root symbol table entry caches the addresses of the B-tree and the heap
+        let root = read_u64(file, 64) as usize;
+        assert_eq!(read_u32(file, 72), 1, "cache type");
+        let cached = (read_u64(file, 80), read_u64(file, 88));
+
+        let messages = read_object_header(file, root);
+        let (_, symbol_table) = messages
+            .iter()
+            .find(|(kind, _)| *kind == 0x0011)
+            .expect("the root group has a symbol table message");
+        let btree = read_u64(symbol_table, 0) as usize;
+        let heap = read_u64(symbol_table, 8) as usize;
+        assert_eq!(cached, (btree as u64, heap as u64));
+
+        assert_eq!(&file[heap..heap + 4], b"HEAP");
+        let heap_size = read_u64(file, heap + 8) as usize;
+        let heap_data = read_u64(file, heap + 24) as usize;
+        let name = |offset: usize| -> String {
+            assert!(offset < heap_size);
+            let start = heap_data + offset;
+            let end = start + file[start..].iter().position(|b| *b == 0).unwrap();
+            String::from_utf8(file[start..end].to_vec()).unwrap()
+        };
+
+        assert_eq!(&file[btree..btree + 4], b"TREE");
+        assert_eq!(
+            (file[btree + 4], file[btree + 5]),
+            (0, 0),
+            "group leaf node"
+        );
+        assert_eq!(read_u16(file, btree + 6), 1, "entries used");
+        let node = read_u64(file, btree + 32) as usize;
+
+        assert_eq!(&file[node..node + 4], b"SNOD");
+        let symbols = read_u16(file, node + 6) as usize;
+        let mut datasets = Vec::new();
+        for i in 0..symbols {
+            let entry = node + 8 + i * SYMBOL_TABLE_ENTRY_SIZE;
+            let name = name(read_u64(file, entry) as usize);
+            let messages = read_object_header(file, read_u64(file, entry + 8) as usize);
+            let message = |kind: u16| {
+                messages
+                    .iter()
+                    .find(|(k, _)| *k == kind)
+                    .map(|(_, data)| *data)
+                    .unwrap()
+            };
+            let dataspace = message(0x0001);
+            assert_eq!(dataspace[0], 1, "dataspace version");
+            let dims: Vec<u64> = (0..dataspace[1] as usize)
+                .map(|n| read_u64(dataspace, 8 + 8 * n))
+                .collect();
+            let datatype = message(0x0003);
+            let layout = message(0x0008);
+            assert_eq!((layout[0], layout[1]), (3, 1), "contiguous layout");
+            let address = read_u64(layout, 2) as usize;
+            let len = read_u64(layout, 10) as usize;
+            let size = read_u32(datatype, 4);
+            assert_eq!(len as u64, dims.iter().product::<u64>() * size as u64);
+            datasets.push(ReadDataset {
+                name,
+                class: datatype[0] & 0x0f,
+                size,
+                dims,
+                data: file[address..address + len].to_vec(),
+            });
+        }
+        // the names in a symbol table node need to be sorted
+        assert!(datasets.windows(2).all(|pair| pair[0].name < pair[1].name));
+        datasets
+    }
+
+    fn floats(data: &[u8]) -> Vec<f64> {
+        data.chunks(8)
+            .map(|bytes| f64::from_le_bytes(bytes.try_into().unwrap()))
+            .collect()
+    }
+
+    /// The values of the test volume in C order
+    fn expected_data() -> Vec<f64> {
+        (0..2)
+            .flat_map(|i| {
+                (0..3).flat_map(move |j| (0..4).map(move |k| (100 * i + 10 * j + k) as f64))
+            })
+            .collect()
+    }
+
+    /// Writes a volume of 2 x 3 x 4 points with the value 100 i + 10 j + k at (i, j, k)
+    fn write_test_volume(path: &Path) {
+        let mut data = Array3d::new(2, 3, 4);
+        for i in 0..2 {
+            for j in 0..3 {
+                for k in 0..4 {
+                    data[(i, j, k)] = (100 * i + 10 * j + k) as f64;
+                }
+            }
+        }
+        let volume = YellVolume {
+            data: &data,
+            lower_limits: [-0.5, -1.0, -1.5],
+            step_sizes: [0.5, 0.5, 0.5],
+            unit_cell: [10.0003, 10.0003, 10.0003, 90.0, 90.0, 90.0],
+            is_direct: true,
+        };
+        volume.write(path).unwrap();
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = std::env::temp_dir().join(format!("pba_yell_{}.h5", std::process::id()));
+        write_test_volume(&path);
+        let file = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let datasets = read_hdf5(&file);
+        let names: Vec<&str> = datasets.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "data",
+                "format",
+                "is_direct",
+                "lower_limits",
+                "step_sizes",
+                "unit_cell"
+            ]
+        );
+        let [data, format, is_direct, lower_limits, step_sizes, unit_cell] = &datasets[..] else {
+            unreachable!()
+        };
+        // C order, the last index runs fastest
+        assert_eq!(
+            (data.class, data.size, &data.dims[..]),
+            (1, 8, &[2, 3, 4][..])
+        );
+        assert_eq!(floats(&data.data), expected_data());
+        assert_eq!((format.class, format.size), (3, 8));
+        assert!(format.dims.is_empty());
+        assert_eq!(format.data, b"Yell 1.0");
+        assert_eq!((is_direct.class, is_direct.size), (0, 8));
+        assert_eq!(
+            i64::from_le_bytes(is_direct.data[..].try_into().unwrap()),
+            1
+        );
+        assert_eq!(floats(&lower_limits.data), [-0.5, -1.0, -1.5]);
+        assert_eq!(floats(&step_sizes.data), [0.5, 0.5, 0.5]);
+        assert_eq!(
+            floats(&unit_cell.data),
+            [10.0003, 10.0003, 10.0003, 90.0, 90.0, 90.0]
+        );
+    }
+
+    /// Runs h5dump of the HDF5 tools and returns its output with single spaces
+    fn h5dump(args: &[&str], path: &Path) -> String {
+        let output = std::process::Command::new("h5dump")
+            .args(args)
+            .arg(path)
+            .output()
+            .expect("h5dump of the HDF5 tools needs to be installed");
+        assert!(
+            output.status.success(),
+            "h5dump could not read the file: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        stdout.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+
+    /// The numbers between the braces of the DATA block of h5dump
+    fn dumped_floats(dump: &str) -> Vec<f64> {
+        let start = dump.find("DATA {").expect("h5dump shows the data") + "DATA {".len();
+        let end = start + dump[start..].find('}').unwrap();
+        dump[start..end]
+            .split([',', ' '])
+            .filter(|value| !value.is_empty())
+            .map(|value| value.parse().unwrap())
+            .collect()
+    }
+
+    /// Checks the file with the HDF5 library itself, like python and Yell read it
+    #[test]
+    #[ignore = "requires h5dump of the HDF5 tools"]
+    fn readable_by_h5dump() {
+        let path = std::env::temp_dir().join(format!("pba_h5dump_{}.h5", std::process::id()));
+        write_test_volume(&path);
+
+        let header = h5dump(&["-H"], &path);
+        for dataset in [
+            r#"DATASET "data" { DATATYPE H5T_IEEE_F64LE DATASPACE SIMPLE { ( 2, 3, 4 ) / ( 2, 3, 4 ) } }"#,
+            r#"DATASET "format" { DATATYPE H5T_STRING { STRSIZE 8; STRPAD H5T_STR_NULLPAD; CSET H5T_CSET_ASCII; CTYPE H5T_C_S1; } DATASPACE SCALAR }"#,
+            r#"DATASET "is_direct" { DATATYPE H5T_STD_I64LE DATASPACE SCALAR }"#,
+            r#"DATASET "lower_limits" { DATATYPE H5T_IEEE_F64LE DATASPACE SIMPLE { ( 3 ) / ( 3 ) } }"#,
+            r#"DATASET "step_sizes" { DATATYPE H5T_IEEE_F64LE DATASPACE SIMPLE { ( 3 ) / ( 3 ) } }"#,
+            r#"DATASET "unit_cell" { DATATYPE H5T_IEEE_F64LE DATASPACE SIMPLE { ( 6 ) / ( 6 ) } }"#,
+        ] {
+            assert!(
+                header.contains(dataset),
+                "{} is missing in {}",
+                dataset,
+                header
+            );
+        }
+        // without indices and line breaks, so only the values are between the braces
+        let values =
+            |dataset: &str| dumped_floats(&h5dump(&["-y", "-w", "0", "-d", dataset], &path));
+        assert_eq!(values("/data"), expected_data());
+        assert_eq!(values("/is_direct"), [1.0]);
+        assert_eq!(values("/lower_limits"), [-0.5, -1.0, -1.5]);
+        assert_eq!(values("/step_sizes"), [0.5, 0.5, 0.5]);
+        assert_eq!(
+            values("/unit_cell"),
+            [10.0003, 10.0003, 10.0003, 90.0, 90.0, 90.0]
+        );
+        assert!(h5dump(&["-d", "/format"], &path).contains(r#""Yell 1.0""#));
+        std::fs::remove_file(&path).unwrap();
+    }
+}